@@ -0,0 +1,175 @@
+//! Stages of conversion on sample images, scaled to several widths.
+//! Run with `cargo bench`, a single stage with e.g. `cargo bench -- from_eq`.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion};
+
+use rast2vec::core::equivalance::{EqKind, Metric};
+use rast2vec::core::{Image, Partition, Polygon};
+use rast2vec::{Options, VectorDocument};
+
+const IMAGES: [&str; 8] = [
+    "anime_girl",
+    "anime_naruto",
+    "blurry",
+    "clean",
+    "icons",
+    "smile",
+    "some",
+    "watermark",
+];
+const WIDTHS: [u32; 3] = [64, 128, 256];
+
+fn path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data")
+        .join(name)
+        .with_extension("png")
+}
+
+/// Every sample image at every width.
+fn scaled() -> Vec<(String, image::RgbaImage)> {
+    let mut r = vec![];
+    for name in IMAGES {
+        let img = image::open(path(name)).unwrap().into_rgba8();
+        for width in WIDTHS {
+            let height = (img.height() * width / img.width()).max(1);
+            let filter = image::imageops::FilterType::Triangle;
+            let scaled = image::imageops::resize(&img, width, height, filter);
+            r.push((format!("{name}_{width}"), scaled));
+        }
+    }
+    r
+}
+
+/// Every sample image at every width, prepared by default options.
+fn inputs() -> Vec<(String, Image)> {
+    let options = Options::default();
+    (scaled().into_iter())
+        .map(|(name, img)| (name, options.prepare(&Image::from(img))))
+        .collect()
+}
+
+fn equivalances() -> Vec<EqKind> {
+    let l1 = EqKind::L1Diff { diff: 10 };
+    vec![
+        l1.clone(),
+        EqKind::L1DiffSup { range: 2, thr: 20 },
+        EqKind::Perceptual {
+            metric: Metric::DeltaE2000,
+            thr: 5.,
+        },
+        EqKind::PaletteIndex { palette: None },
+        EqKind::Transparent {
+            alpha: 0,
+            eq: Box::new(l1.clone()),
+        },
+        EqKind::Edges {
+            sigma: 2.,
+            low: 20.,
+            high: 60.,
+            eq: Some(Box::new(l1)),
+        },
+    ]
+}
+
+fn configure<M: criterion::measurement::Measurement>(group: &mut BenchmarkGroup<M>) {
+    group
+        .sample_size(10)
+        .warm_up_time(Duration::from_millis(500))
+        .measurement_time(Duration::from_secs(2));
+}
+
+/// Scaled images are stored to temporary directory first.
+fn load(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("rast2vec_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut group = c.benchmark_group("load");
+    configure(&mut group);
+    for (name, img) in scaled() {
+        let path = dir.join(&name).with_extension("png");
+        img.save(&path).unwrap();
+        group.bench_function(name, |b| b.iter(|| Image::load(&path, 0).unwrap()));
+    }
+    group.finish();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn from_eq(c: &mut Criterion) {
+    let inputs = inputs();
+    let mut group = c.benchmark_group("from_eq");
+    configure(&mut group);
+    for eq in equivalances() {
+        let name = eq.build().name();
+        for (input, img) in &inputs {
+            group.bench_with_input(BenchmarkId::new(&name, input), img, |b, img| {
+                b.iter(|| Partition::from_eq(img, eq.build().as_mut()))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Stages after partitioning, on partitions by the second default tier.
+fn stages(c: &mut Criterion) {
+    let options = Options::default();
+    let tier = &options.tiers[1];
+    let inputs: Vec<_> = (inputs().into_iter())
+        .map(|(name, img)| {
+            let pn = Partition::from_eq(&img, tier.eq.build().as_mut());
+            (name, img, pn)
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("filter_by_size");
+    configure(&mut group);
+    for (name, _, pn) in &inputs {
+        group.bench_with_input(BenchmarkId::from_parameter(name), pn, |b, pn| {
+            b.iter(|| pn.filter_by_size(tier.range()))
+        });
+    }
+    group.finish();
+
+    let inputs: Vec<_> = (inputs.into_iter())
+        .map(|(name, img, pn)| (name, pn.filter_by_size(tier.range()), img))
+        .collect();
+    let mut group = c.benchmark_group("polygons");
+    configure(&mut group);
+    for (name, pn, img) in &inputs {
+        group.bench_with_input(BenchmarkId::from_parameter(name), img, |b, img| {
+            b.iter(|| Polygon::from_partition(pn, img))
+        });
+    }
+    group.finish();
+
+    // Tracing of shared borders, then their simplification or fitting of curves.
+    let mut group = c.benchmark_group("shapes");
+    configure(&mut group);
+    let curves = Options {
+        curves: Some(Default::default()),
+        ..Default::default()
+    };
+    for (name, pn, img) in &inputs {
+        for (kind, options) in [("simplify", &options), ("curves", &curves)] {
+            group.bench_with_input(BenchmarkId::new(kind, name), img, |b, img| {
+                b.iter(|| VectorDocument::shapes(img, pn, options))
+            });
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("svg");
+    configure(&mut group);
+    for (name, pn, img) in &inputs {
+        let doc = VectorDocument::from_partitions(img, std::slice::from_ref(pn), &options);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &doc, |b, doc| {
+            b.iter(|| doc.svg().doc.to_string())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, load, from_eq, stages);
+criterion_main!(benches);
@@ -0,0 +1,127 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use rast2vec::core::Image;
+use rast2vec::Options;
+
+use crate::config::Config;
+use crate::profile::{Stage, Stages};
+
+pub struct Batch<'a> {
+    config: &'a Config,
+    options: &'a Options,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(config: &'a Config, options: &'a Options) -> Self {
+        Self { config, options }
+    }
+
+    pub fn run(&self, stage: &Stage) -> Result<Summary> {
+        let source = self.config.dir_source.as_ref().unwrap();
+        let mut inputs = vec![];
+        self.collect(source, Path::new(""), &mut inputs)?;
+        inputs.sort();
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.config.jobs.unwrap_or(0))
+            .build()?;
+        let results = pool.install(|| {
+            inputs
+                .par_iter()
+                .map(|input| {
+                    let start = Instant::now();
+                    let stage = stage.start(input.display());
+                    let r =
+                        std::panic::catch_unwind(AssertUnwindSafe(|| self.convert(input, &stage)))
+                            .unwrap_or_else(|panic| Err(panicked(panic)));
+                    stage.stop();
+                    let r = r.map(|skipped| (start.elapsed(), skipped));
+                    (input.clone(), r)
+                })
+                .collect()
+        });
+        Ok(Summary { results })
+    }
+
+    /// Collects rasters from `dir`, their paths are relative to `dir_source`.
+    fn collect(&self, source: &Path, rel: &Path, inputs: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in std::fs::read_dir(source.join(rel))? {
+            let entry = entry?;
+            let path = rel.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                if self.config.recursive {
+                    self.collect(source, &path, inputs)?;
+                }
+                continue;
+            }
+            let is_raster = image::ImageFormat::from_path(&path).is_ok();
+            let matches = match &self.config.glob {
+                Some(glob) => glob.matches_path(&path),
+                None => true,
+            };
+            if is_raster && matches {
+                inputs.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns number of skipped areas.
+    fn convert(&self, input: &Path, stage: &Stage) -> Result<usize> {
+        let img = Image::load(&self.config.source(input), 0)?;
+        let doc = rast2vec::convert_with(&img, self.options, &mut Stages::new(stage))?;
+        let size = img.dim();
+        stage.count("pixels", size.x * size.y);
+        stage.count("shapes", doc.layers.iter().map(|l| l.len()).sum());
+        stage.count("nodes", doc.nodes.iter().flatten().map(|n| n.kept).sum());
+        let output = self.config.workspace.join(input).with_extension("svg");
+        if let Some(dir) = output.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        doc.save(&output)?;
+        if self.config.quality {
+            doc.save_quality(&img, &output)?;
+        }
+        Ok(doc.errors.len())
+    }
+}
+
+/// Error with message of a panic while converting one of files.
+fn panicked(panic: Box<dyn Any + Send>) -> anyhow::Error {
+    let message = (panic.downcast_ref::<&str>().copied())
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    anyhow::anyhow!("panicked: {message}")
+}
+
+pub struct Summary {
+    results: Vec<(PathBuf, Result<(Duration, usize)>)>,
+}
+
+impl Summary {
+    /// Whether conversion of any file failed.
+    pub fn failed(&self) -> bool {
+        self.results.iter().any(|(_, r)| r.is_err())
+    }
+
+    pub fn print(&self) {
+        let ok = self.results.iter().filter(|(_, r)| r.is_ok()).count();
+        eprintln!("converted {ok} of {} images", self.results.len());
+        for (input, r) in &self.results {
+            match r {
+                Ok((took, 0)) => eprintln!("{:50} took {took:?}", input.display()),
+                Ok((took, skipped)) => eprintln!(
+                    "{:50} took {took:?}, skipped {skipped} areas",
+                    input.display()
+                ),
+                Err(e) => eprintln!("{:50} failed: {e:#}", input.display()),
+            }
+        }
+    }
+}
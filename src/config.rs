@@ -1,60 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use rast2vec::core::{CurveOptions, Optimize, Palette, Simplifier};
+use rast2vec::Refine;
+
 #[derive(Debug, clap::Parser, Clone)]
 pub struct Config {
+    /// Images to convert (any format supported by `image`).
+    #[arg(required_unless_present = "batch")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Resulting svg. Treated as directory if several inputs are given.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long)]
+    pub workspace: PathBuf,
+
+    /// Base for relative inputs.
+    #[arg(long)]
+    pub dir_source: Option<PathBuf>,
+
+    /// Toml pipeline description, see `pipelines/default.toml`.
+    #[arg(long)]
+    pub pipeline: Option<PathBuf>,
+
+    /// Fit curves to borders with given tolerance (in pixels), overrides pipeline.
+    #[arg(long)]
+    pub curve_tolerance: Option<f64>,
+
+    /// Simplify borders with given tolerance (in pixels), overrides pipeline.
+    #[arg(long)]
+    pub simplify_tolerance: Option<f64>,
+
+    /// Algorithm used with `--simplify-tolerance`.
+    #[arg(long, value_enum, default_value_t = SimplifierKind::DouglasPeucker, requires = "simplify_tolerance")]
+    pub simplifier: SimplifierKind,
+
+    /// Quantize image to given number of colors before partitioning, overrides pipeline.
     #[arg(long)]
-    pub workspace: std::path::PathBuf,
+    pub colors: Option<usize>,
 
+    /// Quantize image to colors from file (hex, separated by whitespace), overrides pipeline.
     #[arg(long)]
-    pub dir_source: Option<std::path::PathBuf>,
+    pub palette: Option<PathBuf>,
+
+    /// Refine result by given number of passes over residual, overrides pipeline.
+    #[arg(long)]
+    pub refine: Option<usize>,
+
+    /// Tune points and colors of result by given number of iterations, overrides pipeline.
+    #[arg(long)]
+    pub optimize: Option<usize>,
+
+    /// Print number of nodes removed from every shape.
+    #[arg(long)]
+    pub report: bool,
+
+    /// Save hierarchy of areas of the first tier as nested groups to workspace.
+    #[arg(long)]
+    pub tree: bool,
+
+    /// Save result rasterized back to pixels to workspace.
+    #[arg(long)]
+    pub render: bool,
+
+    /// Save quality metrics (json) and difference heatmap (png) next to resulting svg.
+    #[arg(long)]
+    pub quality: bool,
 
     #[arg(short = 't', default_value_t = false)]
     pub timers: bool,
-}
 
-impl Config {
-    #[must_use]
-    pub fn timer<T: ToString>(&self, name: T) -> MaybeTimer {
-        MaybeTimer::start(self.timers, name)
-    }
+    /// Save durations, counts of processed items and peak memory of stages to file.
+    #[arg(long)]
+    pub profile: Option<PathBuf>,
 
-    // pub fn time<T>(&self, name: &str, f: &mut dyn FnMut() -> T) -> T {
-    //     let t = self.timer(name);
-    //     let r = f();
-    //     t.stop();
-    //     r
-    // }
-}
+    /// Format of `--profile`.
+    #[arg(long, value_enum, default_value_t = ProfileFormat::Json, requires = "profile")]
+    pub profile_format: ProfileFormat,
+
+    /// Convert every image in `dir_source`, mirroring its structure in `workspace`.
+    #[arg(long, requires = "dir_source", conflicts_with = "inputs")]
+    pub batch: bool,
 
-#[derive(Debug)]
-pub struct Timer {
-    name: String,
-    start: std::time::Instant,
+    /// Walk subdirectories in batch mode.
+    #[arg(short, long, requires = "batch")]
+    pub recursive: bool,
+
+    /// Convert only files matching pattern (relative to `dir_source`) in batch mode.
+    #[arg(long, requires = "batch")]
+    pub glob: Option<glob::Pattern>,
+
+    /// Number of workers in batch mode, all cores by default.
+    #[arg(short, long, requires = "batch")]
+    pub jobs: Option<usize>,
 }
 
-impl Timer {
-    #[must_use]
-    pub fn start<T: ToString>(name: T) -> Self {
-        Timer {
-            name: name.to_string(),
-            start: std::time::Instant::now(),
+impl Config {
+    pub fn source(&self, input: &Path) -> PathBuf {
+        match &self.dir_source {
+            Some(dir) => dir.join(input),
+            None => input.to_path_buf(),
         }
     }
-    pub fn point(&self) -> std::time::Duration {
-        self.start.elapsed()
+
+    pub fn output(&self, input: &Path) -> PathBuf {
+        let name = Path::new(input.file_stem().unwrap_or_default()).with_extension("svg");
+        match &self.output {
+            Some(output) if self.inputs.len() == 1 => output.clone(),
+            Some(output) => output.join(name),
+            None => self.workspace.join(name),
+        }
     }
-    pub fn stop(self) -> String {
-        format!("{:50} took {:?}", self.name, self.point())
+
+    /// Fails if outputs of several inputs would be stored under the same name.
+    pub fn check_outputs(&self) -> anyhow::Result<()> {
+        let mut stems = std::collections::HashMap::new();
+        for input in &self.inputs {
+            if let Some(other) = stems.insert(input.file_stem(), input) {
+                anyhow::bail!(
+                    "{} and {} have the same name, their outputs would overwrite each other",
+                    other.display(),
+                    input.display()
+                );
+            }
+        }
+        Ok(())
     }
-}
 
-pub struct MaybeTimer(Option<Timer>);
-impl MaybeTimer {
-    pub fn start<T: ToString>(create: bool, name: T) -> Self {
-        match create {
-            true => MaybeTimer(Some(Timer::start(name))),
-            false => MaybeTimer(None),
+    pub fn description(&self) -> anyhow::Result<Description> {
+        let mut r: Description = match &self.pipeline {
+            Some(path) => toml::from_str(&std::fs::read_to_string(path)?)?,
+            None => Description::default(),
+        };
+        if let Some(tolerance) = self.curve_tolerance {
+            let curves = r.options.curves.unwrap_or_default();
+            r.options.curves = Some(CurveOptions {
+                tolerance,
+                ..curves
+            });
+        }
+        if self.colors.is_some() || self.palette.is_some() {
+            let mut quantize = r.options.quantize.unwrap_or_default();
+            if let Some(colors) = self.colors {
+                quantize.colors = colors;
+            }
+            if let Some(path) = &self.palette {
+                quantize.palette = Some(Palette::parse(&std::fs::read_to_string(path)?)?.colors);
+            }
+            r.options.quantize = Some(quantize);
+        }
+        if let Some(passes) = self.refine {
+            let refine = r.options.refine.unwrap_or_default();
+            r.options.refine = Some(Refine { passes, ..refine });
+        }
+        if let Some(iterations) = self.optimize {
+            let optimize = r.options.optimize.unwrap_or_default();
+            r.options.optimize = Some(Optimize {
+                iterations,
+                ..optimize
+            });
         }
+        if let Some(tolerance) = self.simplify_tolerance {
+            r.options.simplify = match self.simplifier {
+                SimplifierKind::DouglasPeucker => Simplifier::DouglasPeucker { tolerance },
+                SimplifierKind::Visvalingam => Simplifier::Visvalingam { tolerance },
+            };
+        }
+        r.options.check()?;
+        Ok(r)
     }
-    pub fn stop(self) {
-        self.0.map(|t| eprintln!("{}", t.stop()));
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum SimplifierKind {
+    DouglasPeucker,
+    Visvalingam,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ProfileFormat {
+    /// Tree of stages.
+    Json,
+    /// Trace events, viewable in `chrome://tracing` or Perfetto.
+    Chrome,
+}
+
+/// Conversion options with intermediate results to be stored in workspace.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Description {
+    #[serde(default)]
+    pub options: rast2vec::Options,
+    #[serde(default = "default_outputs")]
+    pub outputs: Vec<Output>,
+}
+
+impl Default for Description {
+    fn default() -> Self {
+        Self {
+            options: Default::default(),
+            outputs: default_outputs(),
+        }
     }
 }
+
+fn default_outputs() -> Vec<Output> {
+    vec![Output::new("r1".into(), 1), Output::new("r2".into(), 2)]
+}
+
+/// Svg with first `layers` layers, stored as `{input}_{name}.svg`.
+#[derive(Debug, Clone, serde::Deserialize, derive_new::new)]
+#[serde(deny_unknown_fields)]
+pub struct Output {
+    pub name: String,
+    pub layers: usize,
+}
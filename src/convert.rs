@@ -0,0 +1,521 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::core::equivalance::EqKind;
+use crate::core::svg::SVG;
+use crate::core::{
+    BoundaryGraph, Curve, CurveOptions, Hierarchy, Image, Merge, Metrics, Optimize, Partition,
+    Quantize, Rasterizer, Shape, Simplifier, TraceError, V,
+};
+use crate::refine::Refine;
+
+/// One pass of partitioning: areas linked by `eq` with size in `min_size..max_size`,
+/// or all of them (folded by `merge`) with `merge`.
+#[derive(derive_new::new, Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tier {
+    pub eq: EqKind,
+    #[serde(default)]
+    pub min_size: usize,
+    pub max_size: Option<usize>,
+    /// Fold small areas into neighbours, every pixel is kept so size range is ignored.
+    #[new(default)]
+    pub merge: Option<Merge>,
+    /// Cut segmentation hierarchy (after `merge`) at this number of areas.
+    #[new(default)]
+    pub areas: Option<usize>,
+}
+
+impl Tier {
+    pub fn range(&self) -> (usize, Option<usize>) {
+        (self.min_size, self.max_size)
+    }
+
+    pub fn partition(&self, img: &Image) -> Partition {
+        self.partition_with(img, &mut ())
+    }
+
+    /// Same as `partition`, but every step (`from_eq`, `merge`, `hierarchy`, `filter_by_size`
+    /// if not merged) is observed with the number of resulting regions.
+    pub fn partition_with(&self, img: &Image, observer: &mut dyn Observer) -> Partition {
+        let mut step = |name: &str, f: &mut dyn FnMut() -> Partition| {
+            observer.start(name);
+            let pn = f();
+            observer.count("regions", pn.areas.len());
+            observer.stop();
+            pn
+        };
+        let pn = step("from_eq", &mut || {
+            Partition::from_eq(img, self.eq.build().as_mut())
+        });
+        let pn = match &self.merge {
+            Some(merge) => step("merge", &mut || pn.merge(img, merge)),
+            None => pn,
+        };
+        let pn = match self.areas {
+            Some(areas) => step("hierarchy", &mut || {
+                Hierarchy::new(&pn, img).cut_areas(areas)
+            }),
+            None => pn,
+        };
+        match self.merge {
+            Some(_) => pn,
+            None => step("filter_by_size", &mut || pn.filter_by_size(self.range())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    /// Reduce colors before partitioning.
+    pub quantize: Option<Quantize>,
+    /// Applied in order, later tiers are drawn above.
+    pub tiers: Vec<Tier>,
+    pub pad: usize,
+    /// Fit curves to borders, otherwise they are kept as pixel stairs.
+    pub curves: Option<CurveOptions>,
+    /// Applied to borders if `curves` are off.
+    pub simplify: Simplifier,
+    /// Emit areas with zero average alpha, they are skipped otherwise.
+    pub keep_transparent: bool,
+    /// Cover what tiers missed by passes over residual.
+    pub refine: Option<Refine>,
+    /// Tune points and colors of the result to the image.
+    pub optimize: Option<Optimize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let tier = |range, thr, from, to| Tier::new(EqKind::L1DiffSup { range, thr }, from, to);
+        Self {
+            quantize: None,
+            tiers: vec![
+                tier(4, 10, 18000, None),
+                tier(2, 20, 500, Some(18000)),
+                tier(2, 25, 5, Some(500)),
+            ],
+            pad: 10,
+            curves: None,
+            simplify: Simplifier::Collinear,
+            keep_transparent: false,
+            refine: None,
+            optimize: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_toml(s: &str) -> Result<Self> {
+        let r: Self = toml::from_str(s)?;
+        r.check()?;
+        Ok(r)
+    }
+
+    /// Fails if `pad` is less than equivalances of tiers look around pixels,
+    /// or quantization palette is empty.
+    pub fn check(&self) -> Result<()> {
+        anyhow::ensure!(self.pad > 0, "pad must be positive");
+        if let Some(Quantize {
+            palette: Some(p), ..
+        }) = &self.quantize
+        {
+            anyhow::ensure!(!p.is_empty(), "empty palette");
+        }
+        for (n, tier) in self.tiers.iter().enumerate() {
+            let need = tier.eq.pad();
+            anyhow::ensure!(
+                self.pad >= need,
+                "tier {n} needs pad of at least {need}, got {}",
+                self.pad
+            );
+        }
+        Ok(())
+    }
+
+    /// Fully transparent shapes are skipped unless `keep_transparent`.
+    pub fn visible(&self, shape: &Shape) -> bool {
+        self.keep_transparent || shape.color.alpha() > 0
+    }
+
+    /// Padded and quantized image to be partitioned.
+    pub fn prepare(&self, image: &Image) -> Image {
+        let img = image.pad(self.pad);
+        match &self.quantize {
+            Some(q) => q.apply(&img),
+            None => img,
+        }
+    }
+}
+
+/// Result of conversion: one layer of shapes per tier.
+pub struct VectorDocument {
+    /// Of partitioned image, padding included.
+    pub size: V,
+    /// Padding around converted image, cut off by `render`.
+    pub pad: usize,
+    pub layers: Vec<Vec<Shape>>,
+    /// Areas skipped because of tracing errors.
+    pub errors: Vec<TraceError>,
+    /// Same layout as `layers`.
+    pub nodes: Vec<Vec<NodeCount>>,
+}
+
+/// Nodes of closed contours of a shape, as traced and in its path.
+/// Both are counted as segments, which is the same for closed contours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCount {
+    pub traced: usize,
+    pub kept: usize,
+}
+
+impl NodeCount {
+    /// Negative if nodes were added.
+    pub fn removed(&self) -> isize {
+        self.traced as isize - self.kept as isize
+    }
+}
+
+/// What `VectorDocument::improve` did.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Improvement {
+    /// Layers appended by `Options::refine`.
+    pub passes: Option<usize>,
+    /// Error before and after `Options::optimize`, see `Optimize::apply`.
+    pub error: Option<(f64, f64)>,
+}
+
+/// Fidelity and size of the result, see `VectorDocument::quality`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Quality {
+    #[serde(flatten)]
+    pub metrics: Metrics,
+    /// Segments of all paths.
+    pub nodes: usize,
+    pub paths: usize,
+    pub svg_bytes: usize,
+}
+
+impl VectorDocument {
+    /// Expects `img` to be the one partitions were built on.
+    pub fn from_partitions(img: &Image, partitions: &[Partition], options: &Options) -> Self {
+        let (mut errors, mut layers, mut nodes) = (vec![], vec![], vec![]);
+        for pn in partitions {
+            let (mut layer, mut counts) = (vec![], vec![]);
+            for shape in Self::shapes(img, pn, options) {
+                match shape {
+                    Ok((shape, _)) if !options.visible(&shape) => {}
+                    Ok((shape, n)) => {
+                        layer.push(shape);
+                        counts.push(n);
+                    }
+                    Err(e) => errors.push(e),
+                }
+            }
+            layers.push(layer);
+            nodes.push(counts);
+        }
+        Self {
+            size: img.size(),
+            pad: options.pad,
+            layers,
+            errors,
+            nodes,
+        }
+    }
+
+    /// Applies `refine` and then `optimize` of `options` against padded `image`,
+    /// which is expected to be the converted one (before `Options::prepare`, so not quantized).
+    /// Both are observed as steps.
+    pub fn improve(
+        &mut self,
+        image: &Image,
+        options: &Options,
+        observer: &mut dyn Observer,
+    ) -> Improvement {
+        if options.refine.is_none() && options.optimize.is_none() {
+            return Improvement::default();
+        }
+        let img = &image.pad(options.pad);
+        let passes = (options.refine.as_ref()).map(|refine| {
+            observer.start("refine");
+            let passes = self.refine(img, refine, options);
+            observer.count("passes", passes);
+            count_shapes(observer, self);
+            observer.stop();
+            passes
+        });
+        let error = (options.optimize.as_ref()).map(|optimize| {
+            observer.start("optimize");
+            let error = optimize.apply(&mut self.layers, img);
+            observer.count("iterations", optimize.iterations);
+            observer.stop();
+            error
+        });
+        Improvement { passes, error }
+    }
+
+    /// Shape of every area of `pn` with its node count.
+    pub fn shapes(
+        img: &Image,
+        pn: &Partition,
+        options: &Options,
+    ) -> Vec<Result<(Shape, NodeCount), TraceError>> {
+        let mut graph = BoundaryGraph::from_partition(pn);
+        let traced: Vec<_> = graph.edges.iter().map(|e| e.knots.len() - 1).collect();
+        if options.curves.is_none() {
+            graph.simplify(|knots| options.simplify.apply(knots));
+        }
+        let curves: Vec<_> = (graph.edges.iter())
+            .map(|e| match &options.curves {
+                Some(c) => Curve::fit(&e.knots, c),
+                None => Curve::from_knots(&e.knots),
+            })
+            .collect();
+        let shapes = Shape::from_graph(&graph, &curves, pn, img);
+        (shapes.into_iter().zip(&graph.contours))
+            .map(|(shape, contours)| match (shape, contours) {
+                (Ok(shape), Ok(contours)) => {
+                    let sides = contours.frame.iter().chain(contours.holes.iter().flatten());
+                    let curves = std::iter::once(&shape.frame).chain(&shape.holes);
+                    let n = NodeCount {
+                        traced: sides.map(|s| traced[s.edge]).sum(),
+                        kept: curves.map(|c| c.segments.len()).sum(),
+                    };
+                    Ok((shape, n))
+                }
+                (Err(e), _) => Err(e),
+                (_, Err(e)) => Err(e.clone()),
+            })
+            .collect()
+    }
+
+    /// Svg with first `layers` layers.
+    pub fn svg_layers(&self, layers: usize) -> SVG {
+        let mut r = SVG::new(self.size);
+        for layer in self.layers.iter().take(layers) {
+            r.extend_with_shapes(layer);
+        }
+        r
+    }
+    pub fn svg(&self) -> SVG {
+        self.svg_layers(self.layers.len())
+    }
+
+    /// Rasterized first `layers` layers, of the same size as converted image.
+    pub fn render_layers(&self, layers: usize) -> Image {
+        let (pad, size) = (self.pad, self.size);
+        let size = V::new(size.y - pad * 2, size.x - pad * 2);
+        self.render_padded(layers).crop(V::new(pad, pad), size)
+    }
+    pub fn render(&self) -> Image {
+        self.render_layers(self.layers.len())
+    }
+
+    /// Same as `render_layers`, but of the same size as partitioned image.
+    pub(crate) fn render_padded(&self, layers: usize) -> Image {
+        let shapes = self.layers.iter().take(layers).flatten();
+        Rasterizer::default().render(self.size, shapes)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        svg::save(path, &self.svg().doc).map_err(|e| e.into())
+    }
+
+    /// Compares rendered document with `image` it was converted from (before `Options::prepare`).
+    pub fn quality(&self, image: &Image) -> Quality {
+        let (reference, render) = self.compared(image);
+        Quality {
+            metrics: Metrics::new(&reference, &render),
+            nodes: self.nodes.iter().flatten().map(|n| n.kept).sum(),
+            paths: self.layers.iter().map(|l| l.len()).sum(),
+            svg_bytes: self.svg().doc.to_string().len(),
+        }
+    }
+
+    /// Difference of rendered document with `image`, see `Image::heatmap`.
+    pub fn heatmap(&self, image: &Image) -> Image {
+        let (reference, render) = self.compared(image);
+        reference.heatmap(&render)
+    }
+
+    /// Stores `quality` as `{path}.quality.json` and `heatmap` as `{path}.heatmap.png`.
+    pub fn save_quality(&self, image: &Image, path: &Path) -> Result<Quality> {
+        let quality = self.quality(image);
+        let json = serde_json::to_string_pretty(&quality)?;
+        std::fs::write(path.with_extension("quality.json"), json)?;
+        let heatmap = self.heatmap(image);
+        heatmap.store(&path.with_extension("heatmap.png"))?;
+        Ok(quality)
+    }
+
+    /// View of `image` and the same part of rendered document.
+    fn compared(&self, image: &Image) -> (Image, Image) {
+        let view = image.view();
+        let render = self.render().crop(view.from, view.size);
+        (image.crop(view.from, view.size), render)
+    }
+}
+
+/// Svg with shapes of `tree` leaves nested in groups by merges.
+/// Expects `img` to be the one tree was built on.
+pub fn hierarchy_svg(img: &Image, tree: &Hierarchy, options: &Options) -> SVG {
+    let shapes: Vec<_> = (VectorDocument::shapes(img, &tree.base, options).into_iter())
+        .map(|s| s.ok().map(|(s, _)| s).filter(|s| options.visible(s)))
+        .collect();
+    let mut r = SVG::new(img.size());
+    r.extend_with_hierarchy(tree, &shapes);
+    r
+}
+
+/// Hooks into steps of `convert_with`, e.g. to time them or to store intermediate results.
+/// Errors of hooks stop conversion.
+pub trait Observer {
+    /// Step with `name` starts, inside of the current one if any.
+    fn start(&mut self, _name: &str) {}
+    /// Adds `n` to count of items (pixels, regions, shapes...) of the current step.
+    fn count(&mut self, _what: &'static str, _n: usize) {}
+    /// The current step stops.
+    fn stop(&mut self) {}
+
+    /// Image after `Options::prepare`.
+    fn prepared(&mut self, _img: &Image) -> Result<()> {
+        Ok(())
+    }
+    /// Partition by `Options::tiers[tier]` of prepared `img`, inside of the step of tier.
+    fn partitioned(&mut self, _tier: usize, _img: &Image, _pn: &Partition) -> Result<()> {
+        Ok(())
+    }
+    fn improved(&mut self, _improvement: &Improvement) {}
+}
+
+impl Observer for () {}
+
+/// Fails if `options` don't pass `Options::check`.
+pub fn convert(image: &Image, options: &Options) -> Result<VectorDocument> {
+    convert_with(image, options, &mut ())
+}
+
+/// Same as `convert`, steps are reported to `observer`.
+pub fn convert_with(
+    image: &Image,
+    options: &Options,
+    observer: &mut dyn Observer,
+) -> Result<VectorDocument> {
+    options.check()?;
+    observer.start("prepare");
+    let img = options.prepare(image);
+    observer.count("pixels", pixels(&img));
+    observer.stop();
+    observer.prepared(&img)?;
+
+    let mut partitions = vec![];
+    for (i, tier) in options.tiers.iter().enumerate() {
+        observer.start(&format!("tier {}", tier.eq.build().name()));
+        let pn = tier.partition_with(&img, observer);
+        observer.partitioned(i, &img, &pn)?;
+        observer.stop();
+        partitions.push(pn);
+    }
+
+    observer.start("from_partitions");
+    let mut doc = VectorDocument::from_partitions(&img, &partitions, options);
+    count_shapes(observer, &doc);
+    observer.stop();
+    let improvement = doc.improve(image, options, observer);
+    observer.improved(&improvement);
+    Ok(doc)
+}
+
+/// Of view, without padding.
+fn pixels(img: &Image) -> usize {
+    let size = img.dim();
+    size.x * size.y
+}
+
+fn count_shapes(observer: &mut dyn Observer, doc: &VectorDocument) {
+    observer.count("shapes", doc.layers.iter().map(|l| l.len()).sum());
+    observer.count("nodes", doc.nodes.iter().flatten().map(|n| n.kept).sum());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Color;
+
+    #[test]
+    fn default_pipeline() {
+        let pipeline: toml::Table =
+            toml::from_str(include_str!("../pipelines/default.toml")).unwrap();
+        let options = Options::from_toml(&toml::to_string(&pipeline["options"]).unwrap()).unwrap();
+        assert_eq!(options.tiers, Options::default().tiers);
+        assert_eq!(options.pad, Options::default().pad);
+        assert!(Options::from_toml("padding = 10").is_err());
+    }
+
+    #[test]
+    fn transparency() {
+        // Transparent noise on the left, semi-transparent red on the right.
+        let mut img = Image::new(V::new(6, 6), 0);
+        for v in img.view().iter() {
+            img[v] = match v.x < 3 {
+                true => Color::new([(v.y * 40) as u8, (v.x * 80) as u8, 7, 0]),
+                false => Color::new([255, 0, 0, 128]),
+            };
+        }
+        let eq = EqKind::Transparent {
+            alpha: 0,
+            eq: Box::new(EqKind::L1Diff { diff: 10 }),
+        };
+        let mut options = Options {
+            tiers: vec![Tier::new(eq, 1, None)],
+            ..Default::default()
+        };
+
+        let doc = convert(&img, &options).unwrap();
+        assert_eq!(doc.layers[0].len(), 1);
+        let color = doc.layers[0][0].color;
+        assert_eq!(color, Color::new([255, 0, 0, 128]));
+        assert_eq!(color.format_svg_opacity().as_deref(), Some("0.502"));
+
+        options.keep_transparent = true;
+        assert_eq!(convert(&img, &options).unwrap().layers[0].len(), 2);
+    }
+
+    #[test]
+    fn lossless_quality() {
+        let mut img = Image::new(V::new(4, 4), 0);
+        for v in img.view().iter() {
+            img[v] = Color::new([255, 0, 0, 255]);
+        }
+        let options = Options {
+            tiers: vec![Tier::new(EqKind::L1Diff { diff: 10 }, 1, None)],
+            ..Default::default()
+        };
+        let doc = convert(&img, &options).unwrap();
+        assert_eq!(doc.render().pixels(), img.pixels(), "padding is cut off");
+        let quality = doc.quality(&img);
+        assert!(quality.metrics.psnr.is_infinite());
+
+        let json = serde_json::to_string(&quality).unwrap();
+        assert!(json.contains(r#""psnr":null"#), "{json}");
+        let read: Quality = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, quality);
+    }
+
+    #[test]
+    fn pad() {
+        let img = Image::new(V::new(4, 4), 0);
+        let sup = |range| Tier::new(EqKind::L1DiffSup { range, thr: 10 }, 1, None);
+        let options = |pad, tiers| Options {
+            tiers,
+            pad,
+            ..Default::default()
+        };
+        assert!(convert(&img, &options(0, vec![])).is_err());
+        assert!(convert(&img, &options(1, vec![sup(2)])).is_err());
+        assert!(convert(&img, &options(2, vec![sup(2)])).is_ok());
+        assert!(Options::from_toml("pad = 0").is_err());
+    }
+}
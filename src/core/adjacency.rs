@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use super::{Color, Image, Partition};
+
+/// Areas of partition as nodes, pairs of touching areas as edges.
+pub struct RegionGraph {
+    /// Indexed as areas of partition.
+    pub regions: Vec<Region>,
+    /// Sorted by areas.
+    pub edges: Vec<Adjacency>,
+    /// Indices of `edges` of every region.
+    incident: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub size: usize,
+    /// See `Image::color`.
+    pub color: Color,
+    /// Sums of channels over pixels, not weighted by alpha.
+    pub sum: [u64; 4],
+}
+
+/// Border between areas `a` and `b`, `a` is less.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjacency {
+    pub a: usize,
+    pub b: usize,
+    /// Number of pixel sides shared.
+    pub border: usize,
+    /// Average L1 difference of pixels across the border.
+    pub gradient: f64,
+}
+
+impl Adjacency {
+    /// Expects `area` to be one of the ends.
+    pub fn other(&self, area: usize) -> usize {
+        if area == self.a {
+            self.b
+        } else {
+            self.a
+        }
+    }
+}
+
+impl RegionGraph {
+    /// Pixels without area are ignored.
+    pub fn from_partition(pn: &Partition, img: &Image) -> Self {
+        let regions = (pn.areas.iter())
+            .map(|area| Region {
+                size: area.len(),
+                color: img.color(area),
+                sum: area.iter().fold([0; 4], |mut r, &v| {
+                    for (s, c) in r.iter_mut().zip(img[v].rgba()) {
+                        *s += c as u64;
+                    }
+                    r
+                }),
+            })
+            .collect();
+
+        let mut borders: HashMap<(usize, usize), (usize, u64)> = HashMap::new();
+        let view = pn.view();
+        for v in view.iter() {
+            let Some(a) = pn.area(v) else { continue };
+            for n in [v.r(), v.d()] {
+                match pn.area(n) {
+                    Some(b) if a != b => {
+                        let e = borders.entry((a.min(b), a.max(b))).or_default();
+                        e.0 += 1;
+                        e.1 += img.diff_l1_with(v, n) as u64;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut edges: Vec<_> = (borders.into_iter())
+            .map(|((a, b), (border, sum))| Adjacency {
+                a,
+                b,
+                border,
+                gradient: sum as f64 / border as f64,
+            })
+            .collect();
+        edges.sort_by_key(|e| (e.a, e.b));
+
+        let mut incident = vec![vec![]; pn.areas.len()];
+        for (i, e) in edges.iter().enumerate() {
+            incident[e.a].push(i);
+            incident[e.b].push(i);
+        }
+        Self {
+            regions,
+            edges,
+            incident,
+        }
+    }
+
+    pub fn neighbours(&self, area: usize) -> impl Iterator<Item = &Adjacency> + '_ {
+        self.incident[area].iter().map(|&i| &self.edges[i])
+    }
+
+    pub fn adjacency(&self, a: usize, b: usize) -> Option<&Adjacency> {
+        let (a, b) = (a.min(b), a.max(b));
+        self.neighbours(a).find(|e| e.b == b)
+    }
+
+    /// L1 difference of average colors.
+    pub fn contrast(&self, a: usize, b: usize) -> u16 {
+        self.regions[a].color.diff_l1(self.regions[b].color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::equivalance::L1Diff;
+    use crate::core::V;
+
+    #[test]
+    fn region_graph() {
+        // Three vertical stripes, 4 pixels high.
+        let mut img = Image::new(V::new(4, 6), 1);
+        for v in img.view().iter() {
+            img[v] = Color::new([[0, 50, 200][(v.x - 1) / 2], 0, 0, 255]);
+        }
+        let pn = Partition::from_eq(&img, &mut L1Diff::new(10));
+        let graph = RegionGraph::from_partition(&pn, &img);
+
+        assert_eq!(graph.regions.len(), 3);
+        assert_eq!(graph.regions[1].size, 8);
+        assert_eq!(graph.regions[2].sum, [1600, 0, 0, 2040]);
+        assert_eq!(graph.edges.len(), 2);
+        let e = graph.adjacency(2, 1).unwrap();
+        assert_eq!((e.a, e.b, e.border, e.gradient), (1, 2, 4, 150.));
+        assert_eq!(e.other(2), 1);
+        assert!(graph.adjacency(0, 2).is_none());
+        let mut neighbours: Vec<_> = graph.neighbours(1).map(|e| e.other(1)).collect();
+        neighbours.sort();
+        assert_eq!(neighbours, [0, 2]);
+        assert_eq!(graph.contrast(0, 1), 50);
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{Knot, Partition, Polygon, Rect, TraceError, V};
+
+/// Planar graph of borders between areas.
+///
+/// Every border between two areas is traced once and shared by both of them,
+/// so changes of `edges` (like simplification) are seen identically from both sides.
+pub struct BoundaryGraph {
+    pub edges: Vec<Edge>,
+    /// Border of every area in terms of `edges`.
+    pub contours: Vec<Result<Contours, TraceError>>,
+}
+
+/// Chain of knots between junctions or closed loop (first knot equals the last one).
+/// Goes clockwise around `right` area and counterclockwise around `left` one.
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub knots: Vec<Knot>,
+    pub right: Option<usize>,
+    pub left: Option<usize>,
+}
+
+/// Edge in contour, passed from end to start if `reversed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Side {
+    pub edge: usize,
+    pub reversed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Contours {
+    pub frame: Vec<Side>,
+    pub holes: Vec<Vec<Side>>,
+}
+
+impl BoundaryGraph {
+    pub fn from_partition(pn: &Partition) -> Self {
+        let bounds = pn
+            .areas
+            .iter()
+            .flatten()
+            .fold(None, |r: Option<(V, V)>, &v| match r {
+                Some((lu, rd)) => Some((V::min_common(lu, v), V::max_common(rd, v))),
+                None => Some((v, v)),
+            });
+        let bounds = match bounds {
+            Some((lu, rd)) => Rect::new(lu, (rd.y - lu.y + 1, rd.x - lu.x + 1).into()),
+            None => {
+                return Self {
+                    edges: vec![],
+                    contours: vec![],
+                }
+            }
+        };
+        Self::new(bounds, pn.areas.len(), |v| pn.area(v))
+    }
+
+    /// Expects every pixel with some label to be in `bounds` and labels to be less than `count`.
+    pub fn new(bounds: Rect, count: usize, label: impl Fn(V) -> Option<usize>) -> Self {
+        let labels = Labels::new(bounds, label);
+        let mut edges = vec![];
+        let mut visited = HashSet::new();
+
+        let knots = Rect::new(bounds.from, bounds.size + (1, 1).into());
+        for k in knots.iter().map(Knot::new).filter(|&k| labels.junction(k)) {
+            for d in Dir::ALL.into_iter().filter(|&d| labels.crack(k, d)) {
+                if !visited.contains(&Dir::crack(k, d)) {
+                    edges.push(labels.trace(k, d, &mut visited));
+                }
+            }
+        }
+        // Loops without junctions.
+        for k in knots.iter().map(Knot::new) {
+            for d in Dir::ALL.into_iter().filter(|&d| labels.crack(k, d)) {
+                if !visited.contains(&Dir::crack(k, d)) {
+                    edges.push(labels.trace(k, d, &mut visited));
+                }
+            }
+        }
+
+        let mut r = Self {
+            edges,
+            contours: vec![],
+        };
+        r.contours = r.link(count);
+        r
+    }
+
+    /// Applies `f` to every edge. Expects `f` to keep the first and the last knots.
+    pub fn simplify(&mut self, f: impl Fn(&[Knot]) -> Vec<Knot>) {
+        for edge in &mut self.edges {
+            edge.knots = f(&edge.knots);
+        }
+    }
+
+    /// Closed contour of knots.
+    pub fn knots(&self, sides: &[Side]) -> Vec<Knot> {
+        let mut r = vec![];
+        for side in sides {
+            let knots = &self.edges[side.edge].knots;
+            let skip = usize::from(!r.is_empty());
+            match side.reversed {
+                true => r.extend(knots.iter().rev().skip(skip)),
+                false => r.extend(knots.iter().skip(skip)),
+            }
+        }
+        r
+    }
+
+    /// Links edges into contours of areas.
+    fn link(&self, count: usize) -> Vec<Result<Contours, TraceError>> {
+        // Sides with area on the right, by area and start.
+        let mut outs: HashMap<(usize, Knot), smallvec::SmallVec<[Side; 2]>> = HashMap::new();
+        let mut order = vec![vec![]; count];
+        for (n, edge) in self.edges.iter().enumerate() {
+            for (area, reversed) in [(edge.right, false), (edge.left, true)] {
+                if let Some(area) = area {
+                    let side = Side { edge: n, reversed };
+                    outs.entry((area, self.start(side))).or_default().push(side);
+                    order[area].push(side);
+                }
+            }
+        }
+
+        order
+            .into_iter()
+            .enumerate()
+            .map(|(area, sides)| self.contours(&mut outs, area, sides))
+            .collect()
+    }
+
+    fn contours(
+        &self,
+        outs: &mut HashMap<(usize, Knot), smallvec::SmallVec<[Side; 2]>>,
+        area: usize,
+        sides: Vec<Side>,
+    ) -> Result<Contours, TraceError> {
+        let mut frame = None;
+        let mut holes = vec![];
+        for first in sides {
+            let outs_first = outs.get_mut(&(area, self.start(first))).unwrap();
+            if !outs_first.contains(&first) {
+                continue;
+            }
+            outs_first.retain(|s| *s != first);
+            let contour = self.trace(outs, area, first)?;
+            match Polygon::area(&self.knots(&contour)) > 0 {
+                true if frame.is_none() => frame = Some(contour),
+                true => return Err(TraceError::SeveralFrames(self.start(first))),
+                false => holes.push(contour),
+            }
+        }
+        let frame = frame.ok_or(TraceError::Empty)?;
+        Ok(Contours { frame, holes })
+    }
+
+    /// Follows sides around `area` starting with `first` until the loop is closed.
+    /// On corner touch turns right, so area stays as close as possible.
+    fn trace(
+        &self,
+        outs: &mut HashMap<(usize, Knot), smallvec::SmallVec<[Side; 2]>>,
+        area: usize,
+        first: Side,
+    ) -> Result<Vec<Side>, TraceError> {
+        let mut contour = vec![first];
+        loop {
+            let cur = *contour.last().unwrap();
+            let (end, dir) = (self.end(cur), self.dir_last(cur));
+            let nexts = outs.get_mut(&(area, end)).ok_or(TraceError::DeadEnd(end))?;
+            let closing = (end == self.start(first)).then_some(first);
+            let next = [dir.right(), dir, dir.left()]
+                .into_iter()
+                .find_map(|d| {
+                    let mut candidates = nexts.iter().copied().chain(closing);
+                    candidates.find(|&s| self.dir_first(s) == d)
+                })
+                .ok_or(TraceError::DeadEnd(end))?;
+            if next == first {
+                return Ok(contour);
+            }
+            nexts.retain(|s| *s != next);
+            contour.push(next);
+        }
+    }
+
+    /// First two knots of side.
+    fn head(&self, side: Side) -> (Knot, Knot) {
+        let knots = &self.edges[side.edge].knots;
+        let n = knots.len();
+        match side.reversed {
+            true => (knots[n - 1], knots[n - 2]),
+            false => (knots[0], knots[1]),
+        }
+    }
+    /// Last two knots of side.
+    fn tail(&self, side: Side) -> (Knot, Knot) {
+        let (b, a) = self.head(Side {
+            reversed: !side.reversed,
+            ..side
+        });
+        (a, b)
+    }
+    fn start(&self, side: Side) -> Knot {
+        self.head(side).0
+    }
+    fn end(&self, side: Side) -> Knot {
+        self.tail(side).1
+    }
+    fn dir_first(&self, side: Side) -> Dir {
+        let (a, b) = self.head(side);
+        Dir::between(a, b)
+    }
+    fn dir_last(&self, side: Side) -> Dir {
+        let (a, b) = self.tail(side);
+        Dir::between(a, b)
+    }
+}
+
+/// Labels of pixels in bounds with border of `None` around.
+struct Labels {
+    from: V,
+    width: usize,
+    data: Vec<Option<usize>>,
+}
+
+impl Labels {
+    fn new(bounds: Rect, label: impl Fn(V) -> Option<usize>) -> Self {
+        let width = bounds.size.x + 2;
+        let mut data = vec![None; width * (bounds.size.y + 2)];
+        for v in bounds.iter() {
+            data[(v.y - bounds.from.y + 1) * width + v.x - bounds.from.x + 1] = label(v);
+        }
+        Self {
+            from: bounds.from,
+            width,
+            data,
+        }
+    }
+
+    /// Label of pixel `(dy, dx)` around knot, where `(0, 0)` is up-left one.
+    fn cell(&self, k: Knot, dy: usize, dx: usize) -> Option<usize> {
+        self.data[(k.v.y - self.from.y + dy) * self.width + k.v.x - self.from.x + dx]
+    }
+
+    /// Labels on the right and on the left of crack from `k` in direction `d`.
+    fn sides(&self, k: Knot, d: Dir) -> (Option<usize>, Option<usize>) {
+        let (right, left) = match d {
+            Dir::U => ((0, 1), (0, 0)),
+            Dir::R => ((1, 1), (0, 1)),
+            Dir::D => ((1, 0), (1, 1)),
+            Dir::L => ((0, 0), (1, 0)),
+        };
+        (self.cell(k, right.0, right.1), self.cell(k, left.0, left.1))
+    }
+
+    fn crack(&self, k: Knot, d: Dir) -> bool {
+        let (right, left) = self.sides(k, d);
+        right != left
+    }
+
+    /// Knot where three or more areas meet.
+    fn junction(&self, k: Knot) -> bool {
+        let cells = [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(dy, dx)| self.cell(k, dy, dx));
+        let distinct = (0..4).filter(|&i| !cells[..i].contains(&cells[i])).count();
+        distinct > 2
+    }
+
+    /// Follows cracks from `k` in direction `d` until junction or closing the loop.
+    /// Corner touches of two areas aren't junctions, they are passed through
+    /// keeping pixels of the lower area separated, as `None` ones are joined.
+    fn trace(&self, k: Knot, d: Dir, visited: &mut HashSet<(Knot, Dir)>) -> Edge {
+        let (right, left) = self.sides(k, d);
+        let separated = match (right, left) {
+            (Some(r), Some(l)) => r.min(l),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => unreachable!("crack between equal labels"),
+        };
+        let (mut knots, mut cur, mut d) = (vec![k], k, d);
+        loop {
+            visited.insert(Dir::crack(cur, d));
+            cur = d.step(cur);
+            knots.push(cur);
+            if cur == k || self.junction(cur) {
+                return Edge { knots, right, left };
+            }
+            let back = d.right().right();
+            let outs = Dir::ALL
+                .into_iter()
+                .filter(|&n| n != back && self.crack(cur, n));
+            d = match outs.count() {
+                // Corner touch of two areas, turn around the separated one.
+                3 if Some(separated) == right => d.right(),
+                3 => d.left(),
+                _ => Dir::ALL
+                    .into_iter()
+                    .find(|&n| n != back && self.crack(cur, n))
+                    .unwrap(),
+            };
+        }
+    }
+}
+
+/// Direction of a crack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Dir {
+    U,
+    R,
+    D,
+    L,
+}
+
+impl Dir {
+    const ALL: [Dir; 4] = [Dir::U, Dir::R, Dir::D, Dir::L];
+
+    fn step(self, k: Knot) -> Knot {
+        let v = k.v;
+        Knot::new(match self {
+            Dir::U => v.u(),
+            Dir::R => v.r(),
+            Dir::D => v.d(),
+            Dir::L => v.l(),
+        })
+    }
+    fn right(self) -> Self {
+        match self {
+            Dir::U => Dir::R,
+            Dir::R => Dir::D,
+            Dir::D => Dir::L,
+            Dir::L => Dir::U,
+        }
+    }
+    fn left(self) -> Self {
+        self.right().right().right()
+    }
+    /// Expects knots to be neighbours.
+    fn between(a: Knot, b: Knot) -> Self {
+        match (
+            b.v.y as isize - a.v.y as isize,
+            b.v.x as isize - a.v.x as isize,
+        ) {
+            (-1, _) => Dir::U,
+            (1, _) => Dir::D,
+            (_, -1) => Dir::L,
+            _ => Dir::R,
+        }
+    }
+    /// Same key for crack passed in both directions.
+    fn crack(k: Knot, d: Dir) -> (Knot, Dir) {
+        match d {
+            Dir::U => (Dir::U.step(k), Dir::D),
+            Dir::L => (Dir::L.step(k), Dir::R),
+            _ => (k, d),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_edges() {
+        // Left and right halves, right one with a hole.
+        let bounds = Rect::new((1, 1).into(), (4, 5).into());
+        let label = |v: V| match v {
+            V { y: 2, x: 4 } => None,
+            v if v.x < 3 => Some(0),
+            _ => Some(1),
+        };
+        let mut graph = BoundaryGraph::new(bounds, 2, label);
+        let shared: Vec<_> = graph
+            .edges
+            .iter()
+            .filter(|e| e.right.is_some() && e.left.is_some())
+            .collect();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].knots.len(), 5);
+
+        graph.simplify(|knots| vec![knots[0], knots[knots.len() - 1]]);
+        for contours in &graph.contours {
+            let contours = contours.as_ref().unwrap();
+            let frame = graph.knots(&contours.frame);
+            assert_eq!(frame.first(), frame.last());
+        }
+        let right = graph.contours[1].as_ref().unwrap();
+        assert_eq!(right.holes.len(), 1);
+    }
+
+    #[test]
+    fn corner_touch() {
+        // Ring touching itself by corner in every orientation:
+        // pixels of area are separated, so hole joins outside.
+        for corner in [(0, 0), (0, 2), (2, 0), (2, 2)] {
+            let bounds = Rect::new((0, 0).into(), (3, 3).into());
+            let label = |v: V| (v != V::new(1, 1) && v != V::from(corner)).then_some(0);
+            let graph = BoundaryGraph::new(bounds, 1, label);
+            let contours = graph.contours[0].as_ref().unwrap();
+            assert_eq!(Polygon::area(&graph.knots(&contours.frame)), 2 * 7);
+            assert!(contours.holes.is_empty(), "{corner:?}");
+        }
+
+        // Checkerboard of two areas: the lower one is separated.
+        let bounds = Rect::new((0, 0).into(), (2, 2).into());
+        let graph = BoundaryGraph::new(bounds, 2, |v| Some(usize::from(v.y != v.x)));
+        assert!(matches!(
+            graph.contours[0],
+            Err(TraceError::SeveralFrames(_))
+        ));
+        let contours = graph.contours[1].as_ref().unwrap();
+        assert_eq!(Polygon::area(&graph.knots(&contours.frame)), 2 * 2);
+    }
+}
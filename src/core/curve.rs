@@ -0,0 +1,413 @@
+use super::{BoundaryGraph, Color, Image, Knot, Partition, Polygon, TraceError};
+
+#[derive(derive_new::new, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub y: f64,
+    pub x: f64,
+}
+
+impl Point {
+    pub fn dot(self, other: Point) -> f64 {
+        self.y * other.y + self.x * other.x
+    }
+    pub fn len(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+    pub fn dist(self, other: Point) -> f64 {
+        (self - other).len()
+    }
+    /// Zero vector stays zero.
+    pub fn normalize(self) -> Point {
+        match self.len() {
+            l if l > 0. => self * (1. / l),
+            _ => self,
+        }
+    }
+}
+
+impl From<Knot> for Point {
+    fn from(k: Knot) -> Self {
+        Self::new(k.v.y as f64, k.v.x as f64)
+    }
+}
+
+impl std::ops::Add for Point {
+    type Output = Point;
+
+    fn add(self, rhs: Point) -> Point {
+        Point::new(self.y + rhs.y, self.x + rhs.x)
+    }
+}
+impl std::ops::Sub for Point {
+    type Output = Point;
+
+    fn sub(self, rhs: Point) -> Point {
+        Point::new(self.y - rhs.y, self.x - rhs.x)
+    }
+}
+impl std::ops::Mul<f64> for Point {
+    type Output = Point;
+
+    fn mul(self, rhs: f64) -> Point {
+        Point::new(self.y * rhs, self.x * rhs)
+    }
+}
+
+/// Part of curve, ends in the last point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment {
+    Line(Point),
+    Cubic(Point, Point, Point),
+}
+
+impl Segment {
+    pub fn end(self) -> Point {
+        match self {
+            Segment::Line(p) | Segment::Cubic(_, _, p) => p,
+        }
+    }
+}
+
+/// Sequence of segments, closed if ends in `start`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    pub start: Point,
+    pub segments: Vec<Segment>,
+}
+
+impl Curve {
+    pub fn from_knots(knots: &[Knot]) -> Self {
+        Self {
+            start: knots[0].into(),
+            segments: knots[1..]
+                .iter()
+                .map(|&k| Segment::Line(k.into()))
+                .collect(),
+        }
+    }
+
+    pub fn end(&self) -> Point {
+        self.segments.last().map_or(self.start, |s| s.end())
+    }
+
+    pub fn reversed(&self) -> Self {
+        let mut segments = vec![];
+        let mut from = self.start;
+        for &s in &self.segments {
+            segments.push(match s {
+                Segment::Line(_) => Segment::Line(from),
+                Segment::Cubic(c1, c2, _) => Segment::Cubic(c2, c1, from),
+            });
+            from = s.end();
+        }
+        segments.reverse();
+        Self {
+            start: self.end(),
+            segments,
+        }
+    }
+
+    /// Appends `other`, expects it to start at the end of `self`.
+    pub fn extend(&mut self, other: &Curve) {
+        self.segments.extend_from_slice(&other.segments);
+    }
+}
+
+/// Colored area bounded by `frame` with `holes` cut out.
+#[derive(Debug, Clone)]
+pub struct Shape {
+    pub frame: Curve,
+    pub holes: Vec<Curve>,
+    pub color: Color,
+}
+
+impl Shape {
+    /// Builds shapes from `curves`, one per edge of `graph`.
+    /// Expects `graph` to be built from `pn`.
+    pub fn from_graph(
+        graph: &BoundaryGraph,
+        curves: &[Curve],
+        pn: &Partition,
+        src: &Image,
+    ) -> Vec<Result<Self, TraceError>> {
+        let curve = |sides: &[super::Side]| {
+            let mut r: Option<Curve> = None;
+            for side in sides {
+                let c = match side.reversed {
+                    true => curves[side.edge].reversed(),
+                    false => curves[side.edge].clone(),
+                };
+                match &mut r {
+                    Some(r) => r.extend(&c),
+                    None => r = Some(c),
+                }
+            }
+            r.unwrap()
+        };
+        graph
+            .contours
+            .iter()
+            .zip(&pn.areas)
+            .map(|(contours, a)| {
+                let contours = contours.as_ref().map_err(|e| e.clone())?;
+                Ok(Shape {
+                    frame: curve(&contours.frame),
+                    holes: contours.holes.iter().map(|h| curve(h)).collect(),
+                    color: src.color(a),
+                })
+            })
+            .collect()
+    }
+}
+
+impl From<&Polygon> for Shape {
+    fn from(pg: &Polygon) -> Self {
+        Self {
+            frame: Curve::from_knots(&pg.frame),
+            holes: pg.holes.iter().map(|h| Curve::from_knots(h)).collect(),
+            color: pg.color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CurveOptions {
+    /// Max distance (in pixels) between traced knots and fitted curve.
+    pub tolerance: f64,
+    /// Knots with angle (in degrees) sharper than this are kept as corners.
+    pub corner: f64,
+}
+
+impl Default for CurveOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 1.,
+            corner: 120.,
+        }
+    }
+}
+
+/// Number of knots on each side used to estimate angle and tangents.
+const SUPPORT: usize = 3;
+
+impl Curve {
+    /// Fits lines and cubic beziers to chain of knots.
+    ///
+    /// First and last knots, as well as detected corners, are kept in place.
+    /// Other knots are smoothed, so pixel stairs become straight lines or curves.
+    pub fn fit(knots: &[Knot], options: &CurveOptions) -> Self {
+        let raw: Vec<Point> = knots.iter().map(|&k| k.into()).collect();
+        let n = raw.len();
+        let closed = n > 2 && raw[0] == raw[n - 1];
+        // Index of neighbour `d` knots away, wrapping around closed chain.
+        let at = |i: usize, d: isize| -> usize {
+            let m = n as isize - 1;
+            match closed {
+                true => (i as isize + d).rem_euclid(m) as usize,
+                false => (i as isize + d).clamp(0, m) as usize,
+            }
+        };
+
+        let mut points = raw.clone();
+        for i in 1..n.saturating_sub(1) {
+            points[i] = (raw[at(i, -1)] + raw[i] * 2. + raw[at(i, 1)]) * 0.25;
+        }
+
+        let angle = |i: usize| {
+            let d = SUPPORT as isize;
+            let (a, b) = (raw[at(i, -d)] - raw[i], raw[at(i, d)] - raw[i]);
+            let cos = a.dot(b) / (a.len() * b.len()).max(f64::EPSILON);
+            cos.clamp(-1., 1.).acos().to_degrees()
+        };
+        // Near ends of open chain there is not enough support.
+        let supported = |i: usize| closed || (SUPPORT <= i && i + SUPPORT < n);
+        let angles: Vec<f64> = (0..n)
+            .map(|i| if supported(i) { angle(i) } else { 180. })
+            .collect();
+        let mut corners = vec![0];
+        for i in 1..n.saturating_sub(1) {
+            let sharpest = (-(SUPPORT as isize)..=SUPPORT as isize)
+                .map(|d| at(i, d))
+                .all(|j| angles[i] < angles[j] || (angles[i] == angles[j] && i <= j));
+            if angles[i] < options.corner && sharpest {
+                corners.push(i);
+                points[i] = raw[i];
+            }
+        }
+        corners.push(n - 1);
+
+        let mut r = Curve {
+            start: raw[0],
+            segments: vec![],
+        };
+        for w in corners.windows(2) {
+            let pts = &points[w[0]..=w[1]];
+            let (last, k) = (pts.len() - 1, SUPPORT.min(pts.len() - 1));
+            let t1 = (pts[k] - pts[0]).normalize();
+            let t2 = (pts[last - k] - pts[last]).normalize();
+            fit_cubic(pts, t1, t2, options.tolerance, &mut r.segments);
+        }
+        r
+    }
+}
+
+/// Schneider's algorithm: fits cubic with given end tangents, splitting on large error.
+fn fit_cubic(pts: &[Point], t1: Point, t2: Point, tolerance: f64, out: &mut Vec<Segment>) {
+    let (first, last) = (pts[0], pts[pts.len() - 1]);
+    if pts.len() <= 2 {
+        return out.push(Segment::Line(last));
+    }
+    if pts
+        .iter()
+        .all(|&p| dist_to_line(p, first, last) <= tolerance)
+    {
+        return out.push(Segment::Line(last));
+    }
+
+    let mut u = chord_params(pts);
+    let mut bez = generate_bezier(pts, &u, t1, t2);
+    let (mut err, mut split) = max_error(pts, &bez, &u);
+    for _ in 0..4 {
+        if err <= tolerance {
+            break;
+        }
+        u = pts
+            .iter()
+            .zip(&u)
+            .map(|(&p, &t)| newton(&bez, p, t))
+            .collect();
+        bez = generate_bezier(pts, &u, t1, t2);
+        (err, split) = max_error(pts, &bez, &u);
+    }
+    if err <= tolerance {
+        return out.push(Segment::Cubic(bez[1], bez[2], bez[3]));
+    }
+
+    let center = (pts[split - 1] - pts[split + 1]).normalize();
+    fit_cubic(&pts[..=split], t1, center, tolerance, out);
+    fit_cubic(&pts[split..], center * -1., t2, tolerance, out);
+}
+
+pub(super) fn dist_to_line(p: Point, a: Point, b: Point) -> f64 {
+    let d = b - a;
+    match d.len() {
+        l if l > 0. => ((p - a).x * d.y - (p - a).y * d.x).abs() / l,
+        _ => p.dist(a),
+    }
+}
+
+fn chord_params(pts: &[Point]) -> Vec<f64> {
+    let mut u = vec![0.];
+    for w in pts.windows(2) {
+        u.push(u.last().unwrap() + w[0].dist(w[1]));
+    }
+    let total = u.last().unwrap().max(f64::EPSILON);
+    u.iter().map(|t| t / total).collect()
+}
+
+fn bezier(b: &[Point; 4], t: f64) -> Point {
+    let s = 1. - t;
+    b[0] * (s * s * s) + b[1] * (3. * s * s * t) + b[2] * (3. * s * t * t) + b[3] * (t * t * t)
+}
+
+/// Least squares lengths of tangents.
+fn generate_bezier(pts: &[Point], u: &[f64], t1: Point, t2: Point) -> [Point; 4] {
+    let (first, last) = (pts[0], pts[pts.len() - 1]);
+    let (mut c, mut x) = ([[0.; 2]; 2], [0.; 2]);
+    for (&p, &t) in pts.iter().zip(u) {
+        let s = 1. - t;
+        let (b0, b1, b2, b3) = (s * s * s, 3. * s * s * t, 3. * s * t * t, t * t * t);
+        let (a1, a2) = (t1 * b1, t2 * b2);
+        c[0][0] += a1.dot(a1);
+        c[0][1] += a1.dot(a2);
+        c[1][1] += a2.dot(a2);
+        let tmp = p - (first * (b0 + b1) + last * (b2 + b3));
+        x[0] += a1.dot(tmp);
+        x[1] += a2.dot(tmp);
+    }
+    c[1][0] = c[0][1];
+
+    let det = c[0][0] * c[1][1] - c[1][0] * c[0][1];
+    let (alpha1, alpha2) = match det.abs() > 1e-12 {
+        true => (
+            (x[0] * c[1][1] - x[1] * c[0][1]) / det,
+            (c[0][0] * x[1] - c[1][0] * x[0]) / det,
+        ),
+        false => (0., 0.),
+    };
+    let chord = first.dist(last);
+    let eps = 1e-6 * chord;
+    let (alpha1, alpha2) = match alpha1 < eps || alpha2 < eps {
+        true => (chord / 3., chord / 3.),
+        false => (alpha1, alpha2),
+    };
+    [first, first + t1 * alpha1, last + t2 * alpha2, last]
+}
+
+/// Max distance from points to curve and index of the farthest point.
+fn max_error(pts: &[Point], bez: &[Point; 4], u: &[f64]) -> (f64, usize) {
+    let mut r = (0., pts.len() / 2);
+    for i in 1..pts.len() - 1 {
+        let d = bezier(bez, u[i]).dist(pts[i]);
+        if d > r.0 {
+            r = (d, i);
+        }
+    }
+    r
+}
+
+/// Newton step for parameter of point on curve nearest to `p`.
+fn newton(b: &[Point; 4], p: Point, t: f64) -> f64 {
+    let d1 = [(b[1] - b[0]) * 3., (b[2] - b[1]) * 3., (b[3] - b[2]) * 3.];
+    let d2 = [(d1[1] - d1[0]) * 2., (d1[2] - d1[1]) * 2.];
+    let s = 1. - t;
+    let q = bezier(b, t);
+    let q1 = d1[0] * (s * s) + d1[1] * (2. * s * t) + d1[2] * (t * t);
+    let q2 = d2[0] * s + d2[1] * t;
+    let num = (q - p).dot(q1);
+    let den = q1.dot(q1) + (q - p).dot(q2);
+    match den.abs() > f64::EPSILON {
+        true => (t - num / den).clamp(0., 1.),
+        false => t,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::core::{Rect, V};
+
+    #[test]
+    fn fit() {
+        let options = CurveOptions::default();
+
+        let square: HashSet<_> = Rect::new((2, 2).into(), (10, 10).into()).iter().collect();
+        let (frame, _) = Polygon::from_area(&square).unwrap();
+        let curve = Curve::fit(&frame, &options);
+        assert_eq!(curve.segments.len(), 4);
+        assert!(matches!(curve.segments[0], Segment::Line(_)));
+        assert_eq!(curve.end(), curve.start);
+
+        let stairs: Vec<_> = (0..20)
+            .map(|i: usize| Knot::new(V::new(i / 2, i.div_ceil(2))))
+            .collect();
+        let curve = Curve::fit(&stairs, &options);
+        assert_eq!(curve.segments, vec![Segment::Line(Point::new(9., 10.))]);
+
+        let disk: HashSet<_> = Rect::new0((30, 30).into())
+            .iter()
+            .filter(|v| v.sq_dist(V::new(15, 15)) < 12 * 12)
+            .collect();
+        let (frame, _) = Polygon::from_area(&disk).unwrap();
+        let curve = Curve::fit(&frame, &options);
+        assert!(curve.segments.len() < 16);
+        assert!(curve
+            .segments
+            .iter()
+            .any(|s| matches!(s, Segment::Cubic(..))));
+        assert_eq!(curve.reversed().reversed(), curve);
+    }
+}
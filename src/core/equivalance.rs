@@ -1,4 +1,4 @@
-use super::{Rect, V};
+use super::{Color, Lab, Oklab, Palette, Rect, V};
 
 use super::Image;
 
@@ -13,6 +13,9 @@ pub trait Equivalance {
 
     fn name(&self) -> String;
 
+    /// Called once before `linked` is used on `img`, may precompute something.
+    fn prepare(&mut self, _img: &Image) {}
+
     // fn graph(&mut self, img: &Image) -> HashMap<V, smallvec::SmallVec<[V; 4]>> {
     //     img.iter()
     //         .map(|(v, _)| (v, self.linked(v, img, &|_| true)))
@@ -46,6 +49,22 @@ impl Equivalance for L1Diff {
 pub struct L1DiffSup {
     range: usize,
     thr: usize,
+    /// Summed-area table of `Image::diff_l1` for `prepare`d image, with its size.
+    #[new(default)]
+    sums: Option<(V, Vec<u64>)>,
+}
+
+impl L1DiffSup {
+    fn window_sum(&self, window: Rect, img: &Image) -> u32 {
+        match &self.sums {
+            Some((size, sums)) if *size == img.size() => {
+                let at = |v: V| sums[v.y * (size.x + 1) + v.x];
+                let (lu, rd) = (window.lu(), window.rd());
+                (at(rd) + at(lu) - at(V::new(lu.y, rd.x)) - at(V::new(rd.y, lu.x))) as u32
+            }
+            _ => window.iter().map(|v| img.diff_l1(v) as u32).sum(),
+        }
+    }
 }
 
 impl Equivalance for L1DiffSup {
@@ -59,7 +78,7 @@ impl Equivalance for L1DiffSup {
             (with.y - self.range, with.x - self.range).into(),
             V::new(0, 0).pad(self.range * 2 + 1),
         );
-        let sum: u32 = window.iter().map(|v| img.diff_l1(v) as u32).sum();
+        let sum = self.window_sum(window, img);
         let average = sum / 16 / (self.range * 2 + 1) as u32 / (self.range * 2 + 1) as u32;
 
         with.neigh1f(&|v| {
@@ -72,7 +91,418 @@ impl Equivalance for L1DiffSup {
     fn name(&self) -> String {
         format!("l1_range({})_thr({})", self.range, self.thr)
     }
+
+    /// Pixels on the border of `img` have no neighbours, so they are counted as zero.
+    fn prepare(&mut self, img: &Image) {
+        let size = img.size();
+        let mut sums = vec![0; (size.y + 1) * (size.x + 1)];
+        let w = size.x + 1;
+        for v in Rect::new0(size).iter() {
+            let inner = 0 < v.y && v.y + 1 < size.y && 0 < v.x && v.x + 1 < size.x;
+            let d = if inner { img.diff_l1(v) as u64 } else { 0 };
+            sums[(v.y + 1) * w + v.x + 1] =
+                d + sums[v.y * w + v.x + 1] + sums[(v.y + 1) * w + v.x] - sums[v.y * w + v.x];
+        }
+        self.sums = Some((size, sums));
+    }
+}
+
+/// Perceptual color difference, alpha is ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// CIE76 in CIELAB, about 2.3 is just noticeable difference.
+    DeltaE76,
+    /// CIEDE2000 in CIELAB, about 1 is just noticeable difference.
+    DeltaE2000,
+    /// Euclidean in Oklab, about 0.02 is just noticeable difference.
+    Oklab,
+}
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Metric::DeltaE76 => "delta_e76",
+            Metric::DeltaE2000 => "delta_e2000",
+            Metric::Oklab => "oklab",
+        }
+    }
+
+    /// Coordinates used by `dist`.
+    fn convert(self, c: Color) -> [f32; 3] {
+        match self {
+            Metric::DeltaE76 | Metric::DeltaE2000 => {
+                let c = c.lab();
+                [c.l, c.a, c.b]
+            }
+            Metric::Oklab => {
+                let c = c.oklab();
+                [c.l, c.a, c.b]
+            }
+        }
+    }
+
+    fn dist(self, x: [f32; 3], y: [f32; 3]) -> f32 {
+        let lab = |[l, a, b]: [f32; 3]| Lab { l, a, b };
+        let oklab = |[l, a, b]: [f32; 3]| Oklab { l, a, b };
+        match self {
+            Metric::DeltaE76 => lab(x).delta_e76(lab(y)),
+            Metric::DeltaE2000 => lab(x).delta_e2000(lab(y)),
+            Metric::Oklab => oklab(x).dist(oklab(y)),
+        }
+    }
+}
+
+/// Neighbours with `metric` distance less than `thr`.
+#[derive(derive_new::new, Debug)]
+pub struct Perceptual {
+    metric: Metric,
+    thr: f32,
+    /// Converted colors of `prepare`d image, with its size.
+    #[new(default)]
+    colors: Option<(V, Vec<[f32; 3]>)>,
+}
+
+impl Equivalance for Perceptual {
+    fn linked(
+        &mut self,
+        with: V,
+        img: &Image,
+        filter: &dyn Fn(V) -> bool,
+    ) -> smallvec::SmallVec<[V; 4]> {
+        let metric = self.metric;
+        let color = |v: V| match &self.colors {
+            Some((size, colors)) if *size == img.size() => colors[v.y * size.x + v.x],
+            _ => metric.convert(img[v]),
+        };
+        let c = color(with);
+        with.neigh1f(&|v| img.contains(v) && metric.dist(c, color(v)) < self.thr && filter(v))
+    }
+
+    fn name(&self) -> String {
+        format!("{}_thr({})", self.metric.name(), self.thr)
+    }
+
+    fn prepare(&mut self, img: &Image) {
+        let colors = img.pixels().iter().map(|&c| self.metric.convert(c));
+        self.colors = Some((img.size(), colors.collect()));
+    }
+}
+
+/// Links neighbours with the same closest color of `palette`.
+/// Without palette links neighbours with identical colors, as in quantized image.
+#[derive(derive_new::new, Debug)]
+pub struct PaletteIndex {
+    palette: Option<Palette>,
+    /// Palette indices of `prepare`d image, with its size.
+    #[new(default)]
+    indices: Option<(V, Vec<u32>)>,
+}
+
+impl Equivalance for PaletteIndex {
+    fn linked(
+        &mut self,
+        with: V,
+        img: &Image,
+        filter: &dyn Fn(V) -> bool,
+    ) -> smallvec::SmallVec<[V; 4]> {
+        let same = |v: V| match (&self.palette, &self.indices) {
+            (_, Some((size, indices))) if *size == img.size() => {
+                indices[v.y * size.x + v.x] == indices[with.y * size.x + with.x]
+            }
+            (Some(p), _) => p.nearest(img[v]) == p.nearest(img[with]),
+            (None, _) => img[v] == img[with],
+        };
+        with.neigh1f(&|v| img.contains(v) && same(v) && filter(v))
+    }
+
+    fn name(&self) -> String {
+        match &self.palette {
+            Some(p) => format!("palette_index({})", p.colors.len()),
+            None => "palette_index".into(),
+        }
+    }
+
+    fn prepare(&mut self, img: &Image) {
+        if let Some(p) = &self.palette {
+            let mut cache = std::collections::HashMap::new();
+            let indices = (img.pixels().iter())
+                .map(|&c| {
+                    *cache
+                        .entry(c)
+                        .or_insert_with(|| p.nearest(c).map_or(u32::MAX, |i| i as u32))
+                })
+                .collect();
+            self.indices = Some((img.size(), indices));
+        }
+    }
+}
+
+/// Links transparent pixels (with alpha not above `alpha`) only with each other,
+/// other pixels are linked by `inner`.
+#[derive(derive_new::new)]
+pub struct Transparent {
+    alpha: u8,
+    inner: Box<dyn Equivalance>,
+}
+
+impl Transparent {
+    fn transparent(&self, img: &Image, v: V) -> bool {
+        img[v].alpha() <= self.alpha
+    }
+}
+
+impl Equivalance for Transparent {
+    fn linked(
+        &mut self,
+        with: V,
+        img: &Image,
+        filter: &dyn Fn(V) -> bool,
+    ) -> smallvec::SmallVec<[V; 4]> {
+        match self.transparent(img, with) {
+            true => with.neigh1f(&|v| img.contains(v) && self.transparent(img, v) && filter(v)),
+            false => {
+                let alpha = self.alpha;
+                let opaque = |v: V| img[v].alpha() > alpha && filter(v);
+                self.inner.linked(with, img, &opaque)
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("transparent({})_{}", self.alpha, self.inner.name())
+    }
+
+    fn prepare(&mut self, img: &Image) {
+        self.inner.prepare(img)
+    }
+}
+
+/// Doesn't link pixels across Canny edges, other neighbours are linked by optional `inner`.
+/// Edge pixels are not linked at all, so `merge` is expected to fold them into neighbours.
+#[derive(derive_new::new)]
+pub struct Edges {
+    /// Gaussian blur before edge detection, `canny` itself blurs with 1.4, so less has no effect.
+    sigma: f32,
+    /// Hysteresis thresholds of gradient magnitude (up to about 1140).
+    low: f32,
+    high: f32,
+    inner: Option<Box<dyn Equivalance>>,
+    /// Of `prepare`d image.
+    #[new(value = "EdgeMask::default()")]
+    edges: EdgeMask,
+}
+
+impl Edges {
+    const CANNY_SIGMA: f32 = 1.4;
+}
+
+/// Canny edges of view of image, padding is never an edge.
+struct EdgeMask {
+    view: Rect,
+    edges: Vec<bool>,
+}
+
+impl Default for EdgeMask {
+    fn default() -> Self {
+        Self {
+            view: Rect::new0(V::new(0, 0)),
+            edges: vec![],
+        }
+    }
+}
+
+impl EdgeMask {
+    fn get(&self, v: V) -> bool {
+        match self.view.check(v) {
+            Some(v) => {
+                let (x, y) = (v.x - self.view.from.x, v.y - self.view.from.y);
+                self.edges[y * self.view.size.x + x]
+            }
+            None => false,
+        }
+    }
+}
+
+impl Equivalance for Edges {
+    fn linked(
+        &mut self,
+        with: V,
+        img: &Image,
+        filter: &dyn Fn(V) -> bool,
+    ) -> smallvec::SmallVec<[V; 4]> {
+        if self.edges.view != img.view() {
+            self.prepare(img);
+        }
+        let edges = &self.edges;
+        let edge = |v: V| edges.get(v);
+        if edge(with) {
+            return Default::default();
+        }
+        let open = |v: V| img.contains(v) && !edge(v) && filter(v);
+        match &mut self.inner {
+            Some(inner) => inner.linked(with, img, &open),
+            None => with.neigh1f(&open),
+        }
+    }
+
+    fn name(&self) -> String {
+        let name = format!("edges({},{},{})", self.sigma, self.low, self.high);
+        match &self.inner {
+            Some(inner) => format!("{name}_{}", inner.name()),
+            None => name,
+        }
+    }
+
+    fn prepare(&mut self, img: &Image) {
+        let view = img.view();
+        let rgba = image::RgbaImage::from(img.crop(view.from, view.size));
+        let mut gray = image::imageops::grayscale(&rgba);
+        let extra = self.sigma * self.sigma - Self::CANNY_SIGMA * Self::CANNY_SIGMA;
+        if extra > 0. {
+            gray = imageproc::filter::gaussian_blur_f32(&gray, extra.sqrt());
+        }
+        // Canny skips outermost pixels, so view is extended by repeating them.
+        let (w, h) = gray.dimensions();
+        let extended = image::GrayImage::from_fn(w + 2, h + 2, |x, y| {
+            *gray.get_pixel(x.clamp(1, w) - 1, y.clamp(1, h) - 1)
+        });
+        let edges = imageproc::edges::canny(&extended, self.low, self.high);
+        let edges = (1..=h)
+            .flat_map(|y| (1..=w).map(move |x| (x, y)))
+            .map(|(x, y)| edges.get_pixel(x, y).0[0] > 0)
+            .collect();
+        self.edges = EdgeMask { view, edges };
+        if let Some(inner) = &mut self.inner {
+            inner.prepare(img)
+        }
+    }
+}
+
+/// Serializable description of an `Equivalance`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EqKind {
+    L1Diff {
+        diff: usize,
+    },
+    L1DiffSup {
+        range: usize,
+        thr: usize,
+    },
+    Perceptual {
+        metric: Metric,
+        thr: f32,
+    },
+    PaletteIndex {
+        #[serde(default, deserialize_with = "super::quantize::non_empty")]
+        palette: Option<Vec<Color>>,
+    },
+    Transparent {
+        alpha: u8,
+        eq: Box<EqKind>,
+    },
+    Edges {
+        sigma: f32,
+        low: f32,
+        high: f32,
+        #[serde(default)]
+        eq: Option<Box<EqKind>>,
+    },
+}
+
+impl EqKind {
+    /// Padding of image around pixels `linked` looks at.
+    pub fn pad(&self) -> usize {
+        match self {
+            EqKind::L1DiffSup { range, .. } => (*range).max(1),
+            EqKind::Transparent { eq, .. } | EqKind::Edges { eq: Some(eq), .. } => eq.pad(),
+            _ => 1,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Equivalance> {
+        match *self {
+            EqKind::L1Diff { diff } => Box::new(L1Diff::new(diff)),
+            EqKind::L1DiffSup { range, thr } => Box::new(L1DiffSup::new(range, thr)),
+            EqKind::Perceptual { metric, thr } => Box::new(Perceptual::new(metric, thr)),
+            EqKind::PaletteIndex { ref palette } => {
+                Box::new(PaletteIndex::new(palette.clone().map(Palette::new)))
+            }
+            EqKind::Transparent { alpha, ref eq } => Box::new(Transparent::new(alpha, eq.build())),
+            EqKind::Edges {
+                sigma,
+                low,
+                high,
+                ref eq,
+            } => Box::new(Edges::new(
+                sigma,
+                low,
+                high,
+                eq.as_ref().map(|eq| eq.build()),
+            )),
+        }
+    }
 }
 
 // pub struct NormDiffSup<Norm> {}
 // impl<Norm> NormDiffSup<Norm> { fn f() { Norm::f(); } }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Partition;
+
+    #[test]
+    fn edges() {
+        // Smooth gradient in each half with a step between them.
+        let mut img = Image::new(V::new(16, 16), 1);
+        for v in img.view().iter() {
+            let c = (v.x * 2 + if v.x > 8 { 60 } else { 0 }) as u8;
+            img[v] = Color::new([c, c, c, 255]);
+        }
+        let inner = Box::new(L1Diff::new(200));
+        let pn = Partition::from_eq(&img, &mut Edges::new(2., 20., 60., Some(inner)));
+        let (left, right) = (pn.area(V::new(8, 2)), pn.area(V::new(8, 15)));
+        assert!(left.is_some() && right.is_some());
+        assert_ne!(left, right);
+        assert_eq!(pn.area(V::new(1, 2)), left);
+
+        let pn = Partition::from_eq(&img, &mut L1Diff::new(200));
+        assert_eq!(pn.area(V::new(8, 2)), pn.area(V::new(8, 15)));
+
+        // Border of view against padding isn't an edge.
+        let mut img = Image::new(V::new(8, 8), 3);
+        for v in img.view().iter() {
+            img[v] = Color::new([200, 200, 200, 255]);
+        }
+        let pn = Partition::from_eq(&img, &mut Edges::new(2., 20., 60., None));
+        assert_eq!(pn.areas.len(), 1);
+        assert_eq!(pn.areas[0].len(), 64);
+    }
+
+    #[test]
+    fn perceptual() {
+        // Pairs differing by 40 in one channel: bright yellow with a bit of blue
+        // looks nearly the same, gray with more green doesn't.
+        let mut img = Image::new(V::new(2, 2), 1);
+        let rgb = |r, g, b| Color::new([r, g, b, 255]);
+        img[V::new(1, 1)] = rgb(255, 255, 0);
+        img[V::new(1, 2)] = rgb(255, 255, 40);
+        img[V::new(2, 1)] = rgb(100, 100, 100);
+        img[V::new(2, 2)] = rgb(100, 140, 100);
+        let (yellow, gray) = (V::new(1, 1), V::new(2, 1));
+        assert_eq!(img.diff_l1_with(yellow, yellow.r()), 40);
+        assert_eq!(img.diff_l1_with(gray, gray.r()), 40);
+
+        let linked = |thr, v: V| {
+            let mut eq = Perceptual::new(Metric::DeltaE2000, thr);
+            eq.prepare(&img);
+            eq.linked(v, &img, &|_| true).contains(&v.r())
+        };
+        assert!(linked(2., yellow));
+        assert!(!linked(2., gray));
+        assert!(!linked(0.5, yellow));
+        assert!(linked(30., gray));
+    }
+}
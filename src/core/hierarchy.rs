@@ -0,0 +1,127 @@
+use super::merge::Regions;
+use super::partition::DisjointSets;
+use super::{Image, Merge, Partition, RegionGraph};
+
+/// Binary tree of successive merges of neighbouring areas, most similar first.
+///
+/// Nodes `0..leaves` are areas of `base`, node `leaves + i` is the result of `merges[i]`.
+/// Every prefix of `merges` gives consistent (coarser) partition.
+pub struct Hierarchy {
+    /// Complete partition with the finest areas.
+    pub base: Partition,
+    pub merges: Vec<Merged>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Merged {
+    pub children: [usize; 2],
+    /// L1 difference of average colors of children.
+    pub cost: f64,
+    /// The largest cost in subtree, doesn't decrease towards the root.
+    pub level: f64,
+}
+
+impl Hierarchy {
+    /// Pixels without area in `pn` form new areas, see `Partition::merge`.
+    /// Areas are merged until every connected group of them becomes one.
+    pub fn new(pn: &Partition, img: &Image) -> Self {
+        let complete = Merge {
+            min_size: 0,
+            contrast: None,
+        };
+        let base = pn.merge(img, &complete);
+        let leaves = base.areas.len();
+
+        let mut regions = Regions::new(&RegionGraph::from_partition(&base, img));
+        // Current node of every root.
+        let mut node: Vec<_> = (0..leaves).collect();
+        let mut merges: Vec<Merged> = vec![];
+        regions.merge_similar(f64::INFINITY, |a, b, cost| {
+            let children = [node[a], node[b]];
+            let level = (children.iter())
+                .filter(|&&c| c >= leaves)
+                .map(|&c| merges[c - leaves].level)
+                .fold(cost, f64::max);
+            node[a.min(b)] = leaves + merges.len();
+            merges.push(Merged {
+                children,
+                cost,
+                level,
+            });
+        });
+        Self { base, merges }
+    }
+
+    pub fn leaves(&self) -> usize {
+        self.base.areas.len()
+    }
+    pub fn nodes(&self) -> usize {
+        self.leaves() + self.merges.len()
+    }
+
+    /// Partition after first `merges` merges.
+    pub fn cut(&self, merges: usize) -> Partition {
+        let mut sets = DisjointSets::new(self.nodes());
+        for (i, m) in self.merges.iter().take(merges).enumerate() {
+            // Every node is merged once, so children are roots here.
+            for c in m.children {
+                sets.union(c, self.leaves() + i);
+            }
+        }
+        let labels = (self.base.labels().iter())
+            .map(|&l| sets.find(l as usize) as u32)
+            .collect();
+        Partition::from_labels(self.base.view(), labels)
+    }
+
+    /// Coarsest cut with at least `areas` areas (if there are so many leaves).
+    pub fn cut_areas(&self, areas: usize) -> Partition {
+        self.cut(self.leaves().saturating_sub(areas))
+    }
+
+    /// Cut with merges of `level` or less.
+    pub fn cut_level(&self, level: f64) -> Partition {
+        self.cut(self.merges.iter().take_while(|m| m.level <= level).count())
+    }
+
+    /// Nodes which are not merged further.
+    pub fn roots(&self) -> Vec<usize> {
+        let mut merged = vec![false; self.nodes()];
+        for m in &self.merges {
+            m.children.iter().for_each(|&c| merged[c] = true);
+        }
+        (0..self.nodes()).filter(|&n| !merged[n]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::equivalance::L1Diff;
+    use crate::core::{Color, V};
+
+    #[test]
+    fn hierarchy() {
+        // Four vertical stripes: two dark similar ones and two light similar ones.
+        let mut img = Image::new(V::new(3, 8), 1);
+        for v in img.view().iter() {
+            img[v] = Color::new([[0, 20, 200, 230][(v.x - 1) / 2], 0, 0, 255]);
+        }
+        let pn = Partition::from_eq(&img, &mut L1Diff::new(10));
+        let tree = Hierarchy::new(&pn, &img);
+
+        assert_eq!((tree.leaves(), tree.merges.len()), (4, 3));
+        assert_eq!(tree.roots(), [6]);
+        assert_eq!(tree.merges[2].children, [4, 5]);
+        let levels: Vec<_> = tree.merges.iter().map(|m| m.level).collect();
+        assert!(levels.windows(2).all(|w| w[0] <= w[1]));
+
+        assert_eq!(tree.cut(0).areas.len(), 4);
+        let halves = tree.cut_areas(2);
+        halves.check();
+        assert_eq!(halves.areas.len(), 2);
+        assert_eq!(halves.area(V::new(1, 1)), halves.area(V::new(1, 4)));
+        assert_eq!(tree.cut_level(30.).areas.len(), 2);
+        assert_eq!(tree.cut_level(1000.).areas.len(), 1);
+    }
+}
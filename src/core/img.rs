@@ -1,4 +1,3 @@
-use std::collections::HashSet;
 use std::path::Path;
 
 use anyhow::Result;
@@ -10,9 +9,11 @@ use super::Color;
 use super::Rect;
 use super::V;
 
+/// Row-major pixels in one buffer, `pad` pixels around `view` included.
 #[derive(Clone)]
 pub struct Image {
-    data: Vec<Vec<Color>>,
+    data: Vec<Color>,
+    stride: usize,
     view: Rect,
     pad: usize,
 }
@@ -32,11 +33,11 @@ impl Image {
         self.check(v).is_some()
     }
     pub fn get(&self, v: V) -> Option<Color> {
-        self.check(v).map(|v| self.data[v.y][v.x])
+        self.check(v).map(|v| self[v])
     }
     /// Return: `Some(())` on success.
     pub fn set(&mut self, v: V, c: Color) -> Option<()> {
-        self.check(v).map(|v| self.data[v.y][v.x] = c)
+        self.check(v).map(|v| self[v] = c)
     }
     /// Set of actual points.
     pub fn view(&self) -> Rect {
@@ -44,7 +45,24 @@ impl Image {
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (V, Color)> + '_ {
-        self.view.iter().map(|v| (v, self.data[v.y][v.x]))
+        self.view.iter().map(|v| (v, self[v]))
+    }
+
+    /// Whole buffer, padding included.
+    pub fn pixels(&self) -> &[Color] {
+        &self.data
+    }
+    /// Row `y` with padding.
+    pub fn row(&self, y: usize) -> &[Color] {
+        &self.data[y * self.stride..(y + 1) * self.stride]
+    }
+    pub fn row_mut(&mut self, y: usize) -> &mut [Color] {
+        &mut self.data[y * self.stride..(y + 1) * self.stride]
+    }
+    /// Rows of `window`, expects it to be inside of `size`.
+    pub fn window(&self, window: Rect) -> impl Iterator<Item = &[Color]> + '_ {
+        let (from, to) = (window.lu().x, window.rd().x);
+        (window.lu().y..window.rd().y).map(move |y| &self.row(y)[from..to])
     }
 }
 
@@ -52,55 +70,85 @@ impl std::ops::Index<V> for Image {
     type Output = Color;
 
     fn index(&self, index: V) -> &Self::Output {
-        &self.data[index.y][index.x]
+        &self.data[index.y * self.stride + index.x]
     }
 }
 impl std::ops::IndexMut<V> for Image {
     fn index_mut(&mut self, index: V) -> &mut Self::Output {
-        &mut self.data[index.y][index.x]
+        &mut self.data[index.y * self.stride + index.x]
     }
 }
 
+/// Without copying if buffer fits pixels, resulting image has no padding.
+impl From<RgbaImage> for Image {
+    fn from(value: RgbaImage) -> Self {
+        let size = V::new(value.height() as usize, value.width() as usize);
+        let mut raw = value.into_raw();
+        raw.truncate(size.x * size.y * 4);
+        Self {
+            data: cast_vec(raw),
+            stride: size.x,
+            view: Rect::new0(size),
+            pad: 0,
+        }
+    }
+}
+
+/// Padding is cut off, without copying if there's none.
+impl From<Image> for RgbaImage {
+    fn from(value: Image) -> Self {
+        let (w, h) = (value.dim().x as u32, value.dim().y as u32);
+        let data = match value.pad {
+            0 => value.data,
+            _ => value.window(value.view).flatten().copied().collect(),
+        };
+        RgbaImage::from_raw(w, h, cast_vec(data)).unwrap()
+    }
+}
+
+/// Casts without copying if capacity and alignment allow it.
+fn cast_vec<A: bytemuck::Pod, B: bytemuck::Pod>(v: Vec<A>) -> Vec<B> {
+    bytemuck::try_cast_vec(v).unwrap_or_else(|(_, v)| bytemuck::cast_slice(&v).to_vec())
+}
+
 impl Image {
     pub fn new(size: V, pad: usize) -> Self {
-        let data = vec![vec![Default::default(); size.x + pad * 2]; size.y + pad * 2];
+        let stride = size.x + pad * 2;
+        let data = vec![Default::default(); stride * (size.y + pad * 2)];
         let view = Rect::new((pad, pad).into(), size);
-        Self { data, view, pad }
+        Self {
+            data,
+            stride,
+            view,
+            pad,
+        }
     }
 
     pub fn load(path: &Path, pad: usize) -> Result<Self> {
-        let i = image::open(path)?.into_rgba8();
-        let mut r = Self::new((i.height(), i.width()).into(), pad);
-        for v in r.view.iter() {
-            r.set(v, i[((v.x - pad) as u32, (v.y - pad) as u32)].into());
-        }
-        Ok(r)
+        let r = Self::from(image::open(path)?.into_rgba8());
+        Ok(if pad == 0 { r } else { r.pad(pad) })
     }
     pub fn store(&self, path: &Path) -> Result<()> {
-        let mut i = RgbaImage::new(self.dim().x as u32, self.dim().y as u32);
-        for (v, c) in self.iter() {
-            i[((v.x - self.pad) as u32, (v.y - self.pad) as u32)] = c.into();
-        }
-        i.save(path).map_err(|e| e.into())
+        RgbaImage::from(self.clone())
+            .save(path)
+            .map_err(|e| e.into())
     }
     pub fn store_p(&self, path: &Path) -> Result<()> {
-        let mut i = RgbaImage::new(self.size().x as u32, self.size().y as u32);
-        for v in Rect::new0(self.size()).iter() {
-            i[(v.x as u32, v.y as u32)] = self[v].into();
-        }
+        let (w, h) = (self.size().x as u32, self.size().y as u32);
+        let i = RgbaImage::from_raw(w, h, bytemuck::cast_slice(&self.data).to_vec()).unwrap();
         i.save(path).map_err(|e| e.into())
     }
     pub fn crop(&self, from: V, size: V) -> Self {
         let mut r = Self::new(size, 0);
-        for v in Rect::new0(size).iter() {
-            r[v] = self[v + from];
+        for (y, row) in self.window(Rect::new(from, size)).enumerate() {
+            r.row_mut(y).copy_from_slice(row);
         }
         r
     }
     pub fn pad(&self, pad: usize) -> Self {
         let mut r = Self::new(self.size(), pad);
-        for v in Rect::new0(self.size()).iter() {
-            r[v.pad(pad)] = self[v];
+        for y in 0..self.size().y {
+            r.row_mut(y + pad)[pad..pad + self.stride].copy_from_slice(self.row(y));
         }
         r
     }
@@ -111,7 +159,7 @@ impl Image {
         let mut r = Self::new(reference.dim(), 0);
         let colors = reference.colors(partition);
         for v in Rect::new0(r.dim()).iter() {
-            if let Some(&color_ind) = partition.v2area.get(&v.pad(reference.pad)) {
+            if let Some(color_ind) = partition.area(v.pad(reference.pad)) {
                 r[v] = colors[color_ind];
             }
         }
@@ -126,14 +174,23 @@ impl Image {
             .collect()
     }
 
-    pub fn color(&self, area: &HashSet<V>) -> Color {
-        let sum = area.iter().fold([0; 4], |r, &v| r + self[v]);
-        Color::new([
-            (sum[0] / area.len()) as u8,
-            (sum[1] / area.len()) as u8,
-            (sum[2] / area.len()) as u8,
-            (sum[3] / area.len()) as u8,
-        ])
+    /// Channels are weighted by alpha, so colors of (almost) transparent pixels don't matter.
+    pub fn color(&self, area: &[V]) -> Color {
+        let alpha: usize = area.iter().map(|&v| self[v].alpha() as usize).sum();
+        if alpha == 0 {
+            let sum = area.iter().fold([0; 4], |r, &v| r + self[v]);
+            return Color::new(sum.map(|c| (c / area.len()) as u8));
+        }
+        let sum = area.iter().fold([0; 3], |r, &v| {
+            let (c, a) = (self[v].rgb(), self[v].alpha() as usize);
+            [
+                r[0] + c[0] as usize * a,
+                r[1] + c[1] as usize * a,
+                r[2] + c[2] as usize * a,
+            ]
+        });
+        let [r, g, b] = sum.map(|c| (c / alpha) as u8);
+        Color::new([r, g, b, (alpha / area.len()) as u8])
     }
 
     /// Expects v to be good.
@@ -162,3 +219,35 @@ impl Image {
             .sum()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba_image() {
+        let i = RgbaImage::from_fn(5, 3, |x, y| image::Rgba([x as u8, y as u8, 7, 255]));
+        let img = Image::from(i.clone()).pad(2);
+        assert_eq!((img.dim(), img.size()), (V::new(3, 5), V::new(7, 9)));
+        assert_eq!(img[V::new(3, 6)], Color::new([4, 1, 7, 255]));
+        assert_eq!(img.get(V::new(1, 1)), None);
+
+        let window: Vec<_> = img.window(Rect::new(V::new(3, 5), V::new(2, 2))).collect();
+        assert_eq!(
+            window[1],
+            [Color::new([3, 2, 7, 255]), Color::new([4, 2, 7, 255])]
+        );
+        assert_eq!(
+            img.crop(V::new(2, 2), V::new(3, 5)).pixels(),
+            Image::from(i.clone()).pixels()
+        );
+        assert_eq!(RgbaImage::from(img), i);
+
+        // Buffers longer than pixels or with capacity not in whole colors.
+        let mut raw = Vec::with_capacity(63);
+        raw.extend_from_slice(i.as_raw());
+        raw.extend([1, 2]);
+        let odd = RgbaImage::from_raw(5, 3, raw).unwrap();
+        assert_eq!(Image::from(odd).pixels(), Image::from(i.clone()).pixels());
+    }
+}
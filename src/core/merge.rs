@@ -0,0 +1,235 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::partition::{DisjointSets, NONE};
+use super::{Image, Partition, Rect, RegionGraph};
+
+/// Folding of areas into neighbours, see `Partition::merge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Merge {
+    /// Areas with less pixels are merged into neighbours.
+    pub min_size: usize,
+    /// Neighbours with this or less L1 difference of average colors are merged.
+    pub contrast: Option<u16>,
+}
+
+impl Default for Merge {
+    fn default() -> Self {
+        Self {
+            min_size: 5,
+            contrast: None,
+        }
+    }
+}
+
+impl Partition {
+    /// Returns complete partition: every pixel of view belongs to some area.
+    ///
+    /// Pixels without area form new areas first. Then neighbours with low `contrast`
+    /// are merged, most similar first. At last areas smaller than `min_size` are merged,
+    /// smallest first, into neighbour with the least color difference
+    /// per square root of shared border length.
+    pub fn merge(&self, img: &Image, options: &Merge) -> Self {
+        let view = self.view();
+        let mut labels = self.labels().to_vec();
+        fill_unlabeled(&mut labels, view, self.areas.len());
+        let filled = Self::from_labels(view, labels);
+
+        let mut regions = Regions::new(&RegionGraph::from_partition(&filled, img));
+        if let Some(contrast) = options.contrast {
+            regions.merge_similar(contrast as f64, |_, _, _| {});
+        }
+        regions.merge_small(options.min_size);
+
+        let labels = (filled.labels().iter())
+            .map(|&l| regions.sets.find(l as usize) as u32)
+            .collect();
+        Self::from_labels(view, labels)
+    }
+}
+
+/// Every 4-connected component of unlabeled pixels gets a new label, starting from `next`.
+fn fill_unlabeled(labels: &mut [u32], view: Rect, mut next: usize) {
+    let w = view.size.x;
+    for start in 0..labels.len() {
+        if labels[start] != NONE {
+            continue;
+        }
+        labels[start] = next as u32;
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            let neigh = [
+                (i >= w).then(|| i - w),
+                (i % w + 1 < w).then(|| i + 1),
+                (i + w < labels.len()).then(|| i + w),
+                (i % w > 0).then(|| i - 1),
+            ];
+            for n in neigh.into_iter().flatten() {
+                if labels[n] == NONE {
+                    labels[n] = next as u32;
+                    stack.push(n);
+                }
+            }
+        }
+        next += 1;
+    }
+}
+
+/// Areas being merged, data is valid for roots of `sets` only.
+pub(super) struct Regions {
+    sets: DisjointSets,
+    size: Vec<usize>,
+    sum: Vec<[f64; 4]>,
+    /// Shared border length with every neighbour.
+    border: Vec<HashMap<usize, usize>>,
+    /// Changed on every merge, so outdated queue entries are skipped.
+    version: Vec<u32>,
+}
+
+impl Regions {
+    pub(super) fn new(graph: &RegionGraph) -> Self {
+        let n = graph.regions.len();
+        let mut border = vec![HashMap::new(); n];
+        for e in &graph.edges {
+            border[e.a].insert(e.b, e.border);
+            border[e.b].insert(e.a, e.border);
+        }
+        let sum = (graph.regions.iter())
+            .map(|r| r.sum.map(|c| c as f64))
+            .collect();
+        Self {
+            sets: DisjointSets::new(n),
+            size: graph.regions.iter().map(|r| r.size).collect(),
+            sum,
+            border,
+            version: vec![0; n],
+        }
+    }
+
+    /// L1 difference of average colors.
+    fn diff(&self, a: usize, b: usize) -> f64 {
+        let (sa, sb) = (self.sum[a], self.sum[b]);
+        let (na, nb) = (self.size[a] as f64, self.size[b] as f64);
+        (0..4).map(|i| (sa[i] / na - sb[i] / nb).abs()).sum()
+    }
+
+    /// Expects roots, returns root of merged area.
+    fn union(&mut self, a: usize, b: usize) -> usize {
+        self.sets.union(a, b);
+        let (root, child) = (a.min(b), a.max(b));
+        self.size[root] += self.size[child];
+        for i in 0..4 {
+            self.sum[root][i] += self.sum[child][i];
+        }
+        for (n, len) in std::mem::take(&mut self.border[child]) {
+            self.border[n].remove(&child);
+            if n != root {
+                *self.border[n].entry(root).or_default() += len;
+                *self.border[root].entry(n).or_default() += len;
+            }
+        }
+        self.version[root] += 1;
+        self.version[child] += 1;
+        root
+    }
+
+    /// Calls `merged` with merged roots and their difference.
+    pub(super) fn merge_similar(
+        &mut self,
+        contrast: f64,
+        mut merged: impl FnMut(usize, usize, f64),
+    ) {
+        // Differences are scaled to be ordered as integers.
+        let key = |d: f64| (d * 1024.) as u64;
+        let mut queue = BinaryHeap::new();
+        for a in 0..self.size.len() {
+            for &b in self.border[a].keys().filter(|&&b| a < b) {
+                queue.push(Reverse((key(self.diff(a, b)), a, b, 0, 0)));
+            }
+        }
+        while let Some(Reverse((d, a, b, va, vb))) = queue.pop() {
+            if (va, vb) != (self.version[a], self.version[b]) {
+                continue;
+            }
+            if d > key(contrast) {
+                break;
+            }
+            merged(a, b, self.diff(a, b));
+            let r = self.union(a, b);
+            for &n in self.border[r].keys() {
+                let (a, b) = (r.min(n), r.max(n));
+                let (va, vb) = (self.version[a], self.version[b]);
+                queue.push(Reverse((key(self.diff(a, b)), a, b, va, vb)));
+            }
+        }
+    }
+
+    fn merge_small(&mut self, min_size: usize) {
+        let mut queue: BinaryHeap<_> = (0..self.size.len())
+            .filter(|&a| self.size[a] < min_size)
+            .map(|a| Reverse((self.size[a], a, 0)))
+            .collect();
+        while let Some(Reverse((_, a, version))) = queue.pop() {
+            if version != self.version[a] {
+                continue;
+            }
+            let cost = |(&n, &len): (&usize, &usize)| self.diff(a, n) / (len as f64).sqrt();
+            let best = (self.border[a].iter())
+                .map(|e| (cost(e), Reverse(*e.1), *e.0))
+                .min_by(|x, y| x.0.total_cmp(&y.0).then((x.1, x.2).cmp(&(y.1, y.2))));
+            // Area without neighbours stays as is.
+            let Some((_, _, n)) = best else { continue };
+            let r = self.union(a, n);
+            if self.size[r] < min_size {
+                queue.push(Reverse((self.size[r], r, self.version[r])));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::equivalance::{EqKind, L1Diff};
+    use crate::core::{Color, V};
+
+    #[test]
+    fn merge() {
+        // Halves with different colors and a spot of noise in each.
+        let mut img = Image::new(V::new(6, 6), 1);
+        for v in img.view().iter() {
+            img[v] = Color::new([if v.x < 4 { 100 } else { 130 }, 0, 0, 255]);
+        }
+        img[V::new(3, 2)] = Color::new([255, 255, 0, 255]);
+        img[V::new(3, 5)] = Color::new([255, 255, 255, 255]);
+
+        let pn = Partition::from_eq(&img, &mut L1Diff::new(10)).filter_by_size((2, None));
+        assert_eq!(pn.areas.len(), 2);
+        assert_eq!(pn.area(V::new(3, 2)), None);
+
+        let merged = pn.merge(&img, &Merge::default());
+        merged.check();
+        assert_eq!(merged.areas.iter().map(|a| a.len()).sum::<usize>(), 36);
+        assert_eq!(merged.areas.len(), 2);
+        assert_eq!(merged.area(V::new(3, 2)), Some(0));
+        assert_eq!(merged.area(V::new(3, 5)), Some(1));
+
+        let options = Merge {
+            min_size: 0,
+            contrast: Some(40),
+        };
+        let merged = pn.merge(&img, &options);
+        merged.check();
+        assert_eq!(merged.areas.len(), 3);
+        assert_eq!(merged.area(V::new(1, 1)), merged.area(V::new(1, 6)));
+
+        // Merged areas aren't dropped by size range of tier.
+        let tier = crate::Tier {
+            merge: Some(Merge::default()),
+            ..crate::Tier::new(EqKind::L1Diff { diff: 10 }, 0, Some(10))
+        };
+        let pn = tier.partition(&img);
+        assert_eq!(pn.areas.iter().map(|a| a.len()).sum::<usize>(), 36);
+    }
+}
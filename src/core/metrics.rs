@@ -0,0 +1,136 @@
+use super::{Color, Image, Rect, V};
+
+/// Fidelity of `result` to `reference`, over rgba channels of their views.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Metrics {
+    /// Peak signal-to-noise ratio in dB, infinite for identical images (`null` in json).
+    #[serde(with = "infinite_as_null")]
+    pub psnr: f64,
+    /// Mean structural similarity over 8x8 windows (with step 4), 1 for identical images.
+    pub ssim: f64,
+    /// Mean absolute difference of every channel.
+    pub mean_error: [f64; 4],
+}
+
+impl Metrics {
+    /// Expects views of the same size.
+    pub fn new(reference: &Image, result: &Image) -> Self {
+        let pairs = pairs(reference, result);
+        let n = reference.dim().x * reference.dim().y;
+        let (mut squares, mut errors) = (0., [0.; 4]);
+        for (a, b) in pairs.map(|(a, b)| (a.rgba(), b.rgba())) {
+            for ch in 0..4 {
+                let d = a[ch] as f64 - b[ch] as f64;
+                squares += d * d;
+                errors[ch] += d.abs();
+            }
+        }
+        let mse = squares / (n * 4).max(1) as f64;
+        Self {
+            psnr: 10. * (255. * 255. / mse).log10(),
+            ssim: ssim(reference, result),
+            mean_error: errors.map(|e| e / n.max(1) as f64),
+        }
+    }
+}
+
+/// Json has no infinity, so it is stored as `null`.
+mod infinite_as_null {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &f64, s: S) -> Result<S::Ok, S::Error> {
+        match v.is_infinite() {
+            true => s.serialize_none(),
+            false => s.serialize_f64(*v),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<f64, D::Error> {
+        Ok(Option::<f64>::deserialize(d)?.unwrap_or(f64::INFINITY))
+    }
+}
+
+impl Image {
+    /// Per-pixel L1 difference with `other` over views of the same size,
+    /// from black through red and yellow to white at 255 and more.
+    pub fn heatmap(&self, other: &Image) -> Image {
+        let mut r = Image::new(self.dim(), 0);
+        for (v, (a, b)) in r.view().iter().zip(pairs(self, other)) {
+            let t = (a.diff_l1(b) as f64 / 255.).min(1.) * 3.;
+            let ch = |from: f64| ((t - from).clamp(0., 1.) * 255.).round() as u8;
+            r[v] = Color::new([ch(0.), ch(1.), ch(2.), 255]);
+        }
+        r
+    }
+}
+
+/// Colors of the same positions in views.
+fn pairs<'a>(a: &'a Image, b: &'a Image) -> impl Iterator<Item = (Color, Color)> + 'a {
+    assert_eq!(a.dim(), b.dim(), "images of different size");
+    a.iter().zip(b.iter()).map(|((_, a), (_, b))| (a, b))
+}
+
+fn ssim(a: &Image, b: &Image) -> f64 {
+    const WINDOW: usize = 8;
+    const STEP: usize = 4;
+    let (c1, c2) = ((0.01f64 * 255.).powi(2), (0.03f64 * 255.).powi(2));
+    let size = a.dim();
+    let window = V::new(size.y.min(WINDOW), size.x.min(WINDOW));
+    let (mut sum, mut count) = (0., 0);
+    for y in (0..=size.y - window.y).step_by(STEP) {
+        for x in (0..=size.x - window.x).step_by(STEP) {
+            let at = |img: &Image| Rect::new(img.view().from + V::new(y, x), window);
+            let (wa, wb) = (at(a), at(b));
+            let n = (window.x * window.y) as f64;
+            for ch in 0..4 {
+                let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0., 0., 0., 0., 0.);
+                for (va, vb) in wa.iter().zip(wb.iter()) {
+                    let (x, y) = (a[va].rgba()[ch] as f64, b[vb].rgba()[ch] as f64);
+                    (sa, sb) = (sa + x, sb + y);
+                    (saa, sbb, sab) = (saa + x * x, sbb + y * y, sab + x * y);
+                }
+                let (ma, mb) = (sa / n, sb / n);
+                let (va, vb, cov) = (saa / n - ma * ma, sbb / n - mb * mb, sab / n - ma * mb);
+                sum += (2. * ma * mb + c1) * (2. * cov + c2)
+                    / ((ma * ma + mb * mb + c1) * (va + vb + c2));
+                count += 1;
+            }
+        }
+    }
+    match count {
+        0 => 1.,
+        _ => sum / count as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics() {
+        let mut img = Image::new(V::new(16, 16), 2);
+        for v in img.view().iter() {
+            img[v] = Color::new([(v.x * 15) as u8, (v.y * 15) as u8, 100, 255]);
+        }
+        let same = Metrics::new(&img, &img.crop(img.view().from, img.dim()));
+        assert!(same.psnr.is_infinite());
+        assert!((same.ssim - 1.).abs() < 1e-9);
+        assert_eq!(same.mean_error, [0.; 4]);
+
+        let mut noisy = img.clone();
+        for v in img.view().iter().filter(|v| (v.x + v.y) % 2 == 0) {
+            noisy[v] = Color::new([0, 0, 0, 255]);
+        }
+        let m = Metrics::new(&img, &noisy);
+        assert!(m.psnr < 20. && m.ssim < 0.5, "{m:?}");
+        assert_eq!(m.mean_error[3], 0.);
+        assert_eq!(m.mean_error[2], 50.);
+
+        let heatmap = img.heatmap(&noisy);
+        assert_eq!(heatmap.dim(), img.dim());
+        assert_eq!(heatmap[V::new(0, 1)], Color::new([0, 0, 0, 255]));
+        // Difference of 160 of 255.
+        assert_eq!(heatmap[V::new(0, 0)], Color::new([255, 225, 0, 255]));
+    }
+}
@@ -1,12 +1,33 @@
 pub mod equivalance;
 pub mod svg;
 
+mod adjacency;
+mod boundary;
+mod curve;
+mod hierarchy;
 mod img;
+mod merge;
+mod metrics;
+mod optimize;
 mod partition;
 mod polygon;
+mod quantize;
+mod raster;
+mod residual;
+mod simplify;
 
-pub use crate::utility::{Color, Rect, TwoDimIter, V};
+pub use crate::utility::{Color, Lab, Oklab, Rect, TwoDimIter, V};
 
+pub use adjacency::{Adjacency, Region, RegionGraph};
+pub use boundary::{BoundaryGraph, Contours, Edge, Side};
+pub use curve::{Curve, CurveOptions, Point, Segment, Shape};
+pub use hierarchy::{Hierarchy, Merged};
 pub use img::Image;
+pub use merge::Merge;
+pub use metrics::Metrics;
+pub use optimize::Optimize;
 pub use partition::Partition;
-pub use polygon::Polygon;
+pub use polygon::{Knot, Polygon, TraceError};
+pub use quantize::{Method, Palette, Quantize};
+pub use raster::Rasterizer;
+pub use simplify::Simplifier;
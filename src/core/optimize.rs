@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+
+use smallvec::SmallVec;
+
+use super::{Color, Curve, Image, Point, Segment, Shape};
+
+/// Gradient descent (Adam) of points and colors of shapes to the image,
+/// over soft rasterization: coverage falls smoothly across `softness` of border.
+/// Alpha of shapes is kept.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Optimize {
+    pub iterations: usize,
+    /// Step for points, in pixels.
+    pub learning_rate: f64,
+    /// Step for colors, with channels in `0..1`.
+    pub color_learning_rate: f64,
+    /// Half width of soft border (in pixels) at start, it is narrowed to half of pixel.
+    pub softness: f64,
+}
+
+impl Default for Optimize {
+    fn default() -> Self {
+        Self {
+            iterations: 20,
+            learning_rate: 0.05,
+            color_learning_rate: 0.005,
+            softness: 2.,
+        }
+    }
+}
+
+impl Optimize {
+    /// Softness of rendered anti-aliased borders, the last iterations are annealed to it.
+    const SHARP: f64 = 0.5;
+
+    /// Shapes of `layers` are drawn in order over transparent background.
+    /// Equal points of shapes of one layer are kept equal, so shared borders stay shared.
+    /// Returns mean squared error (of premultiplied rgba in `0..1`) over view of `target`
+    /// before and after, with sharp borders; if error grows, shapes are left as is.
+    pub fn apply(&self, layers: &mut [Vec<Shape>], target: &Image) -> (f64, f64) {
+        let mut model = Model::new(layers);
+        let mut adam = Adam::new(model.points.len() * 2 + model.colors.len() * 4);
+        let initial = model.loss(target, Self::SHARP, None);
+        for i in 0..self.iterations {
+            let t = i as f64 / self.iterations.saturating_sub(1).max(1) as f64;
+            let soft = self.softness * (1. - t) + Self::SHARP.min(self.softness) * t;
+            let mut grad = Grad::new(&model);
+            model.loss(target, soft, Some(&mut grad));
+            adam.step(i + 1, &mut model, &grad, self);
+        }
+        let loss = model.loss(target, Self::SHARP, None);
+        if loss >= initial {
+            return (initial, initial);
+        }
+        model.store(layers);
+        (initial, loss)
+    }
+}
+
+/// Parameters of shapes, curves refer to `points` by indices.
+struct Model {
+    points: Vec<Point>,
+    /// Straight rgba in `0..1`, one per shape, alpha is not optimized.
+    colors: Vec<[f64; 4]>,
+    shapes: Vec<ShapeModel>,
+}
+
+struct ShapeModel {
+    /// Every curve as point indices in order of `Curve` fields, and if segments are cubic.
+    curves: Vec<(Vec<usize>, Vec<bool>)>,
+    evenodd: bool,
+}
+
+/// Model points with their weights.
+type Weights = SmallVec<[(usize, f64); 4]>;
+
+/// Flattened outline of shape, points are weighted sums of model points.
+struct Flat {
+    points: Vec<(Point, Weights)>,
+    /// Lines between `points` as `(top, bottom, winding direction)`.
+    lines: Vec<(usize, usize, i32)>,
+    /// Bounding box as `(top, left, bottom, right)`.
+    bounds: (f64, f64, f64, f64),
+}
+
+/// Coverage of pixel by shape, with the closest line if pixel is within soft border.
+struct Fragment {
+    shape: usize,
+    coverage: f64,
+    /// Line of `Flat`, position of the closest point on it and derivative of coverage by it.
+    line: Option<(usize, f64, Point)>,
+}
+
+struct Grad {
+    points: Vec<Point>,
+    colors: Vec<[f64; 4]>,
+}
+
+impl Grad {
+    fn new(model: &Model) -> Self {
+        Self {
+            points: vec![Point::default(); model.points.len()],
+            colors: vec![[0.; 4]; model.colors.len()],
+        }
+    }
+}
+
+fn curve_points(curve: &Curve) -> (Vec<Point>, Vec<bool>) {
+    let mut points = vec![curve.start];
+    let mut cubic = vec![];
+    for &s in &curve.segments {
+        match s {
+            Segment::Line(p) => points.push(p),
+            Segment::Cubic(c1, c2, p) => points.extend([c1, c2, p]),
+        }
+        cubic.push(matches!(s, Segment::Cubic(..)));
+    }
+    (points, cubic)
+}
+
+impl Model {
+    fn new(layers: &[Vec<Shape>]) -> Self {
+        let (mut points, mut colors, mut shapes) = (vec![], vec![], vec![]);
+        for layer in layers {
+            let mut index = HashMap::new();
+            for shape in layer {
+                let mut curves = vec![];
+                for curve in std::iter::once(&shape.frame).chain(&shape.holes) {
+                    let (ps, cubic) = curve_points(curve);
+                    let ids = (ps.into_iter())
+                        .map(|p| {
+                            *index
+                                .entry((p.y.to_bits(), p.x.to_bits()))
+                                .or_insert_with(|| {
+                                    points.push(p);
+                                    points.len() - 1
+                                })
+                        })
+                        .collect();
+                    curves.push((ids, cubic));
+                }
+                shapes.push(ShapeModel {
+                    curves,
+                    evenodd: !shape.holes.is_empty(),
+                });
+                colors.push(shape.color.rgba().map(|c| c as f64 / 255.));
+            }
+        }
+        Self {
+            points,
+            colors,
+            shapes,
+        }
+    }
+
+    fn store(&self, layers: &mut [Vec<Shape>]) {
+        let mut models = self.shapes.iter().zip(&self.colors);
+        for shape in layers.iter_mut().flatten() {
+            let (model, color) = models.next().unwrap();
+            let curves = std::iter::once(&mut shape.frame).chain(&mut shape.holes);
+            for (curve, (ids, _)) in curves.zip(&model.curves) {
+                let mut ps = ids.iter().map(|&i| self.points[i]);
+                curve.start = ps.next().unwrap();
+                for s in &mut curve.segments {
+                    *s = match s {
+                        Segment::Line(_) => Segment::Line(ps.next().unwrap()),
+                        Segment::Cubic(..) => {
+                            let (c1, c2, p) = (ps.next(), ps.next(), ps.next());
+                            Segment::Cubic(c1.unwrap(), c2.unwrap(), p.unwrap())
+                        }
+                    };
+                }
+            }
+            shape.color = Color::new(color.map(|c| (c * 255.).round() as u8));
+        }
+    }
+
+    fn flatten(&self, shape: &ShapeModel) -> Flat {
+        let mut flat = Flat {
+            points: vec![],
+            lines: vec![],
+            bounds: (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        };
+        for (ids, cubic) in &shape.curves {
+            let first = flat.points.len();
+            flat.points
+                .push((self.points[ids[0]], smallvec::smallvec![(ids[0], 1.)]));
+            let mut at = 0;
+            for &is_cubic in cubic {
+                if !is_cubic {
+                    let i = ids[at + 1];
+                    flat.points
+                        .push((self.points[i], smallvec::smallvec![(i, 1.)]));
+                    at += 1;
+                    continue;
+                }
+                let ctrl = [ids[at], ids[at + 1], ids[at + 2], ids[at + 3]];
+                let p = ctrl.map(|i| self.points[i]);
+                let len = p[0].dist(p[1]) + p[1].dist(p[2]) + p[2].dist(p[3]);
+                let n = ((len / 2.).ceil() as usize).clamp(2, 16);
+                for k in 1..=n {
+                    let t = k as f64 / n as f64;
+                    let u = 1. - t;
+                    let w = [u * u * u, 3. * u * u * t, 3. * u * t * t, t * t * t];
+                    let pos = (0..4).fold(Point::default(), |s, j| s + p[j] * w[j]);
+                    flat.points
+                        .push((pos, (0..4).map(|j| (ctrl[j], w[j])).collect()));
+                }
+                at += 3;
+            }
+            let last = flat.points.len() - 1;
+            for (a, b) in (first..last).map(|i| (i, i + 1)).chain([(last, first)]) {
+                let (pa, pb) = (flat.points[a].0, flat.points[b].0);
+                match pa.y.total_cmp(&pb.y) {
+                    std::cmp::Ordering::Less => flat.lines.push((a, b, 1)),
+                    std::cmp::Ordering::Greater => flat.lines.push((b, a, -1)),
+                    // Horizontal lines are needed for distance only.
+                    std::cmp::Ordering::Equal if pa.x != pb.x => flat.lines.push((a, b, 0)),
+                    std::cmp::Ordering::Equal => {}
+                }
+            }
+        }
+        for (p, _) in &flat.points {
+            let b = &mut flat.bounds;
+            *b = (b.0.min(p.y), b.1.min(p.x), b.2.max(p.y), b.3.max(p.x));
+        }
+        flat
+    }
+
+    /// Pushes fragments of shape for pixels of view of `target`.
+    fn fragments(
+        &self,
+        n: usize,
+        flat: &Flat,
+        target: &Image,
+        soft: f64,
+        fragments: &mut [Vec<Fragment>],
+    ) {
+        let view = target.view();
+        let (lu, rd) = (view.lu(), view.rd());
+        let range = |from: f64, to: f64, min: usize, max: usize| {
+            let from = ((from - soft).floor().max(min as f64) as usize).min(max);
+            let to = ((to + soft).ceil().max(min as f64) as usize).min(max);
+            from..to
+        };
+        let (top, left, bottom, right) = flat.bounds;
+        let evenodd = self.shapes[n].evenodd;
+        let point = |i: usize| flat.points[i].0;
+
+        let mut crossings: Vec<(f64, i32)> = vec![];
+        for y in range(top, bottom, lu.y, rd.y) {
+            let yc = y as f64 + 0.5;
+            let near: Vec<_> = (0..flat.lines.len())
+                .filter(|&l| {
+                    let (a, b, _) = flat.lines[l];
+                    point(a).y - soft < yc && yc < point(b).y + soft
+                })
+                .collect();
+            crossings.clear();
+            for &l in &near {
+                let (a, b, dir) = flat.lines[l];
+                let (pa, pb) = (point(a), point(b));
+                if dir != 0 && pa.y <= yc && yc < pb.y {
+                    let x = pa.x + (pb.x - pa.x) * (yc - pa.y) / (pb.y - pa.y);
+                    crossings.push((x, dir));
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let (mut next, mut winding) = (0, 0);
+            for x in range(left, right, lu.x, rd.x) {
+                let p = Point::new(yc, x as f64 + 0.5);
+                while next < crossings.len() && crossings[next].0 < p.x {
+                    winding += crossings[next].1;
+                    next += 1;
+                }
+                let inside = match evenodd {
+                    true => winding % 2 != 0,
+                    false => winding != 0,
+                };
+                let closest = (near.iter())
+                    .map(|&l| {
+                        let (a, b, _) = flat.lines[l];
+                        let (pa, pb) = (point(a), point(b));
+                        let ab = pb - pa;
+                        let t = match ab.dot(ab) {
+                            l2 if l2 > 0. => ((p - pa).dot(ab) / l2).clamp(0., 1.),
+                            _ => 0.,
+                        };
+                        (p.dist(pa + ab * t), l, t, pa + ab * t)
+                    })
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+                let fragment = match closest {
+                    Some((d, l, t, q)) if d < soft && d > 0. => {
+                        let sign = if inside { 1. } else { -1. };
+                        let u = (sign * d + soft) / (2. * soft);
+                        let slope = 6. * u * (1. - u) / (2. * soft);
+                        // Signed distance grows as closest point moves towards pixel from inside.
+                        let by_q = (p - q) * (-sign / d * slope);
+                        Fragment {
+                            shape: n,
+                            coverage: u * u * (3. - 2. * u),
+                            line: Some((l, t, by_q)),
+                        }
+                    }
+                    _ if inside => Fragment {
+                        shape: n,
+                        coverage: 1.,
+                        line: None,
+                    },
+                    _ => continue,
+                };
+                let i = (y - lu.y) * view.size.x + (x - lu.x);
+                fragments[i].push(fragment);
+            }
+        }
+    }
+
+    /// Mean squared error, with gradient if asked.
+    fn loss(&self, target: &Image, soft: f64, mut grad: Option<&mut Grad>) -> f64 {
+        let view = target.view();
+        let flats: Vec<_> = self.shapes.iter().map(|s| self.flatten(s)).collect();
+        let mut fragments: Vec<Vec<Fragment>> =
+            (0..view.size.x * view.size.y).map(|_| vec![]).collect();
+        for (n, flat) in flats.iter().enumerate() {
+            self.fragments(n, flat, target, soft, &mut fragments);
+        }
+
+        let mut loss = 0.;
+        let mut prefix: Vec<[f64; 4]> = vec![];
+        for (v, fragments) in view.iter().zip(&fragments) {
+            let t = target[v].rgba().map(|c| c as f64 / 255.);
+            let t = [t[0] * t[3], t[1] * t[3], t[2] * t[3], t[3]];
+            let mut p = [0.; 4];
+            prefix.clear();
+            for f in fragments {
+                let c = self.colors[f.shape];
+                let (a, s) = (f.coverage * c[3], [c[0], c[1], c[2], 1.]);
+                prefix.push(p);
+                p = [0, 1, 2, 3].map(|k| p[k] * (1. - a) + s[k] * a);
+            }
+            loss += (0..4).map(|k| (p[k] - t[k]).powi(2)).sum::<f64>();
+
+            let Some(grad) = grad.as_deref_mut() else {
+                continue;
+            };
+            let mut g = [0, 1, 2, 3].map(|k| 2. * (p[k] - t[k]));
+            for (f, prev) in fragments.iter().zip(&prefix).rev() {
+                let c = self.colors[f.shape];
+                let (a, s) = (f.coverage * c[3], [c[0], c[1], c[2], 1.]);
+                let by_a: f64 = (0..4).map(|k| g[k] * (s[k] - prev[k])).sum();
+                for (c, g) in grad.colors[f.shape].iter_mut().zip(g).take(3) {
+                    *c += g * a;
+                }
+                if let Some((l, t, by_q)) = f.line {
+                    let flat = &flats[f.shape];
+                    let (pa, pb, _) = flat.lines[l];
+                    let by_q = by_q * (by_a * c[3]);
+                    for (p, w) in [(pa, 1. - t), (pb, t)] {
+                        for &(i, wi) in &flat.points[p].1 {
+                            grad.points[i] = grad.points[i] + by_q * (w * wi);
+                        }
+                    }
+                }
+                g = g.map(|g| g * (1. - a));
+            }
+        }
+        loss / (view.size.x * view.size.y * 4).max(1) as f64
+    }
+}
+
+/// Adam state, points go first as `y, x`, then colors.
+struct Adam {
+    m: Vec<f64>,
+    v: Vec<f64>,
+}
+
+impl Adam {
+    const B1: f64 = 0.9;
+    const B2: f64 = 0.999;
+
+    fn new(n: usize) -> Self {
+        Self {
+            m: vec![0.; n],
+            v: vec![0.; n],
+        }
+    }
+
+    fn step(&mut self, t: usize, model: &mut Model, grad: &Grad, options: &Optimize) {
+        let (c1, c2) = (1. - Self::B1.powi(t as i32), 1. - Self::B2.powi(t as i32));
+        let mut update = |i: usize, g: f64, lr: f64| {
+            self.m[i] = Self::B1 * self.m[i] + (1. - Self::B1) * g;
+            self.v[i] = Self::B2 * self.v[i] + (1. - Self::B2) * g * g;
+            -lr * (self.m[i] / c1) / ((self.v[i] / c2).sqrt() + 1e-12)
+        };
+        for (i, (p, g)) in model.points.iter_mut().zip(&grad.points).enumerate() {
+            p.y += update(2 * i, g.y, options.learning_rate);
+            p.x += update(2 * i + 1, g.x, options.learning_rate);
+        }
+        let offset = model.points.len() * 2;
+        for (i, (c, g)) in model.colors.iter_mut().zip(&grad.colors).enumerate() {
+            for k in 0..3 {
+                let d = update(offset + 4 * i + k, g[k], options.color_learning_rate);
+                c[k] = (c[k] + d).clamp(0., 1.);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Rasterizer, V};
+
+    #[test]
+    fn optimize() {
+        // Target is red square 4..10, shape starts shifted and with wrong color.
+        let mut target = Image::new(V::new(14, 14), 0);
+        for v in target.view().iter() {
+            let inside = (4..10).contains(&v.y) && (4..10).contains(&v.x);
+            target[v] = match inside {
+                true => Color::new([200, 0, 0, 255]),
+                false => Color::new([0, 0, 0, 255]),
+            };
+        }
+        let square = |from: f64, to: f64| Curve {
+            start: Point::new(from, from),
+            segments: [(from, to), (to, to), (to, from), (from, from)]
+                .map(|(y, x)| Segment::Line(Point::new(y, x)))
+                .to_vec(),
+        };
+        let background = Shape {
+            frame: square(0., 14.),
+            holes: vec![],
+            color: Color::new([0, 0, 0, 255]),
+        };
+        let mut shape = background.clone();
+        shape.frame = square(5., 10.);
+        shape.color = Color::new([150, 40, 0, 255]);
+        let mut layers = vec![vec![background], vec![shape]];
+
+        let options = Optimize {
+            iterations: 100,
+            color_learning_rate: 0.01,
+            ..Default::default()
+        };
+        let (before, after) = options.apply(&mut layers, &target);
+        assert!(after < before / 10., "{before} {after}");
+        let shape = &layers[1][0];
+        assert!(
+            shape.frame.start.dist(Point::new(4., 4.)) < 0.3,
+            "{shape:?}"
+        );
+        let [r, g, _, _] = shape.color.rgba();
+        assert!(r.abs_diff(200) < 15 && g < 15, "{shape:?}");
+        assert_eq!(shape.color.alpha(), 255);
+
+        let render = Rasterizer::default().render(V::new(14, 14), layers.iter().flatten());
+        assert_eq!(render[V::new(0, 0)], Color::new([0, 0, 0, 255]));
+        assert_eq!(render[V::new(7, 7)], layers[1][0].color);
+    }
+}
@@ -1,73 +1,189 @@
-use std::collections::{HashMap, HashSet};
-
-use super::V;
+use super::{Rect, V};
 
 use super::equivalance::Equivalance;
 use super::Image;
 
+/// Label of pixels without area.
+pub(super) const NONE: u32 = u32::MAX;
+
 pub struct Partition {
-    pub areas: Vec<HashSet<V>>,
-    pub v2area: HashMap<V, usize>,
+    /// Pixels of every area in row-major order.
+    pub areas: Vec<Vec<V>>,
+    /// Row-major area of every pixel of `view`, `NONE` if it has no area.
+    labels: Vec<u32>,
+    view: Rect,
 }
 
 impl Partition {
-    pub fn from_eq(img: &Image, eq: &mut impl Equivalance) -> Self {
-        let mut visited = HashSet::new();
-        let mut areas = Vec::new();
-        let mut v2area = HashMap::new();
-        for (start, _) in img.iter() {
-            if visited.contains(&start) {
-                continue;
+    /// Areas are components of pixels linked by `eq` in both directions,
+    /// numbered in order of their first pixel.
+    pub fn from_eq(img: &Image, eq: &mut (impl Equivalance + ?Sized)) -> Self {
+        eq.prepare(img);
+        let view = img.view();
+        let (h, w) = (view.size.y, view.size.x);
+        let index = |v: V| (v.y - view.from.y) * w + (v.x - view.from.x);
+
+        // Directions (as in `V::neigh1`) linked from every pixel.
+        let links: Vec<u8> = view
+            .iter()
+            .map(|v| {
+                let linked = eq.linked(v, img, &|_| true);
+                let bit = |(i, n): (usize, V)| (linked.contains(&n) as u8) << i;
+                v.neigh1().into_iter().enumerate().map(bit).sum()
+            })
+            .collect();
+        let (up, right, down, left) = (1, 2, 4, 8);
+
+        let mut sets = DisjointSets::new(h * w);
+        for v in view.iter() {
+            let i = index(v);
+            if v.x + 1 < view.rd().x && links[i] & right != 0 && links[i + 1] & left != 0 {
+                sets.union(i, i + 1);
+            }
+            if v.y + 1 < view.rd().y && links[i] & down != 0 && links[i + w] & up != 0 {
+                sets.union(i, i + w);
             }
-            let mut queue = vec![start];
-            let mut surface = HashSet::from_iter([start].into_iter());
-            while let Some(cur) = queue.pop() {
-                let next = eq.linked(cur, img, &|v| !surface.contains(&v));
-                for next in next {
-                    let nn = eq.linked(next, img, &|_| true);
-                    if nn.contains(&cur) {
-                        surface.insert(next);
-                        queue.push(next);
-                    }
-                }
+        }
+
+        let labels = (0..h * w).map(|i| sets.find(i) as u32).collect();
+        Self::from_labels(view, labels)
+    }
+
+    /// Areas are numbered in order of their first pixel,
+    /// `labels` are row-major for `view` and less than their number (or `NONE`).
+    pub(super) fn from_labels(view: Rect, mut labels: Vec<u32>) -> Self {
+        let mut renumber = vec![NONE; labels.len()];
+        let mut areas: Vec<Vec<V>> = vec![];
+        for (l, v) in labels.iter_mut().zip(view.iter()) {
+            if *l == NONE {
+                continue;
             }
-            visited.extend(surface.iter());
-            for &v in &surface {
-                v2area.insert(v, areas.len());
-                visited.insert(v);
+            if renumber[*l as usize] == NONE {
+                renumber[*l as usize] = areas.len() as u32;
+                areas.push(vec![]);
             }
-            areas.push(surface);
+            *l = renumber[*l as usize];
+            areas[*l as usize].push(v);
+        }
+        Self {
+            areas,
+            labels,
+            view,
         }
-        Self { areas, v2area }
+    }
+
+    /// Pixels outside of the view have no area.
+    pub fn area(&self, v: V) -> Option<usize> {
+        let v = self.view.check(v)?;
+        let i = (v.y - self.view.from.y) * self.view.size.x + (v.x - self.view.from.x);
+        (self.labels[i] != NONE).then_some(self.labels[i] as usize)
+    }
+    pub fn view(&self) -> Rect {
+        self.view
+    }
+    /// Row-major for `view`, `NONE` for pixels without area.
+    pub(super) fn labels(&self) -> &[u32] {
+        &self.labels
     }
 
     pub fn check(&self) {
-        for (v, &n) in &self.v2area {
-            assert!(self.areas[n].contains(v));
+        for v in self.view.iter() {
+            if let Some(n) = self.area(v) {
+                assert!(self.areas[n].contains(&v));
+            }
         }
         for (n, area) in self.areas.iter().enumerate() {
-            for v in area {
-                assert_eq!(self.v2area.get(v), Some(&n));
+            for &v in area {
+                assert_eq!(self.area(v), Some(n));
             }
         }
     }
 
     pub fn filter_by_size(&self, range: (usize, Option<usize>)) -> Self {
         let (from, to) = (range.0, range.1.unwrap_or(usize::MAX));
-        let areas: Vec<_> = self
-            .areas
-            .iter()
-            .enumerate()
-            .filter(|(_, area)| from <= area.len() && area.len() < to)
-            .map(|(old, _)| self.areas[old].clone())
-            .collect();
-        let mut v2area = HashMap::new();
-        for (n, area) in areas.iter().enumerate() {
-            for &v in area {
-                v2area.insert(v, n);
+        let mut renumber = vec![NONE; self.areas.len()];
+        let mut areas = vec![];
+        for (old, area) in self.areas.iter().enumerate() {
+            if from <= area.len() && area.len() < to {
+                renumber[old] = areas.len() as u32;
+                areas.push(area.clone());
             }
         }
+        let labels = (self.labels.iter())
+            .map(|&l| match l {
+                NONE => NONE,
+                l => renumber[l as usize],
+            })
+            .collect();
+
+        Self {
+            areas,
+            labels,
+            view: self.view,
+        }
+    }
+}
+
+/// Union-find over `0..n`, root of every set is its least element.
+pub(super) struct DisjointSets {
+    parent: Vec<u32>,
+}
+
+impl DisjointSets {
+    pub(super) fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n as u32).collect(),
+        }
+    }
+
+    pub(super) fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] as usize != i {
+            // Path halving.
+            let grand = self.parent[self.parent[i] as usize];
+            self.parent[i] = grand;
+            i = grand as usize;
+        }
+        i
+    }
+
+    pub(super) fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        let (root, child) = (a.min(b), a.max(b));
+        self.parent[child] = root as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::equivalance::L1Diff;
+    use crate::core::Color;
+
+    #[test]
+    fn from_eq() {
+        // Two vertical stripes, right one with a distinct pixel inside.
+        let mut img = Image::new(V::new(4, 6), 1);
+        for v in img.view().iter() {
+            img[v] = Color::new([if v.x < 4 { 0 } else { 200 }, 0, 0, 255]);
+        }
+        img[V::new(2, 6)] = Color::new([0, 0, 90, 255]);
+
+        let pn = Partition::from_eq(&img, &mut L1Diff::new(10));
+        pn.check();
+        assert_eq!(
+            pn.areas.iter().map(|a| a.len()).collect::<Vec<_>>(),
+            [12, 11, 1]
+        );
+        assert_eq!(pn.area(V::new(1, 1)), Some(0));
+        assert_eq!(pn.area(V::new(1, 5)), Some(1));
+        assert_eq!(pn.area(V::new(0, 0)), None);
 
-        Self { areas, v2area }
+        let pn = pn.filter_by_size((2, Some(12)));
+        pn.check();
+        assert_eq!(pn.areas.len(), 1);
+        assert_eq!(
+            (pn.area(V::new(1, 1)), pn.area(V::new(1, 5))),
+            (None, Some(0))
+        );
     }
 }
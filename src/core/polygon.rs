@@ -2,119 +2,114 @@ use std::collections::HashSet;
 
 // use crate::utility::debug::pos_debug_hashset;
 
-use super::{Color, Image, Partition, V};
+use super::{BoundaryGraph, Color, Image, Partition, Rect, V};
 
+/// Area bounded by `frame` with `holes` cut out.
+/// Every contour is closed: its first knot equals the last one.
 pub struct Polygon {
     pub frame: Vec<Knot>,
+    pub holes: Vec<Vec<Knot>>,
     pub color: Color,
 }
 
 impl Polygon {
-    pub fn from_partition(pn: &Partition, src: &Image) -> Vec<Self> {
-        pn.areas
+    /// Areas which can't be traced are reported as errors in place of polygons.
+    pub fn from_partition(pn: &Partition, src: &Image) -> Vec<Result<Self, TraceError>> {
+        Self::from_graph(&BoundaryGraph::from_partition(pn), pn, src)
+    }
+
+    /// Expects `graph` to be built from `pn`.
+    pub fn from_graph(
+        graph: &BoundaryGraph,
+        pn: &Partition,
+        src: &Image,
+    ) -> Vec<Result<Self, TraceError>> {
+        graph
+            .contours
             .iter()
-            .map(|a| Polygon {
-                frame: Self::from_area(a),
-                color: src.color(a),
+            .zip(&pn.areas)
+            .map(|(contours, a)| {
+                let contours = contours.as_ref().map_err(|e| e.clone())?;
+                Ok(Polygon {
+                    frame: graph.knots(&contours.frame),
+                    holes: contours.holes.iter().map(|h| graph.knots(h)).collect(),
+                    color: src.color(a),
+                })
             })
             .collect()
     }
 
-    fn from_area(area: &HashSet<V>) -> Vec<Knot> {
-        let knots: HashSet<_> = area
-            .iter()
-            .flat_map(|&v| Knot::from_v(v))
-            .filter(|&k| !k.neigh_v(&|v| !area.contains(&v)).is_empty())
-            .collect();
+    /// Returns outer contour and contours of holes.
+    ///
+    /// Contours go clockwise (with area on the right), so outer one has
+    /// positive `area` and holes have negative.
+    /// Pixels touching only by corner are separated.
+    pub fn from_area(area: &HashSet<V>) -> Result<(Vec<Knot>, Vec<Vec<Knot>>), TraceError> {
+        let lu = area.iter().copied().reduce(V::min_common);
+        let rd = area.iter().copied().reduce(V::max_common);
+        let (lu, rd) = lu.zip(rd).ok_or(TraceError::Empty)?;
+        let bounds = Rect::new(lu, (rd.y - lu.y + 1, rd.x - lu.x + 1).into());
+        let graph = BoundaryGraph::new(bounds, 1, |v| area.contains(&v).then_some(0));
+        let contours = graph.contours[0].as_ref().map_err(|e| e.clone())?;
+        let holes = contours.holes.iter().map(|h| graph.knots(h)).collect();
+        Ok((graph.knots(&contours.frame), holes))
+    }
 
-        let knot_my = knots.iter().map(|&k| k.v.y).min().unwrap();
-        let knot_mx = knots
-            .iter()
-            .filter(|&k| k.v.y == knot_my)
-            .map(|&k| k.v.x)
-            .min()
-            .unwrap();
-        let start = Knot {
-            v: V::new(knot_my, knot_mx),
-        };
-        if !knots.contains(&start) {
-            dbg!(knots, start);
-            panic!()
-        }
+    /// Doubled signed area of closed contour.
+    pub fn area(contour: &[Knot]) -> isize {
+        contour
+            .windows(2)
+            .map(|w| {
+                let (a, b) = (w[0].v, w[1].v);
+                a.x as isize * b.y as isize - b.x as isize * a.y as isize
+            })
+            .sum()
+    }
+}
 
-        // for y in 10..25 {
-        //     let mut row = Vec::from_iter(knots.iter().filter(|k| k.v.y == y).map(|&k| k));
-        //     row.sort_by(|a, b| a.v.x.cmp(&b.v.x));
-        //     dbg!(row);
-        // }
-        // eprintln!("debug area:\n{}", pos_debug_hashset(&area));
-
-        let start_2 = start.neigh(&|k| knots.contains(&k))[0];
-        let mut frame = vec![start, start_2];
-        while frame.first() != frame.last() {
-            let len = frame.len();
-            let (prev, cur) = (frame[len - 2], frame[len - 1]);
-            let nexts = cur.neigh(&|k| {
-                let r = k != prev
-                    && knots.contains(&k)
-                    && matches!(
-                        Knot::between(cur, k),
-                        BetweenKnotPair::Straight([a, b]) if area.contains(&a) != area.contains(&b)
-                    );
-                // dbg!((r, cur, k, Knot::between(cur, k)));
-                r
-            });
-
-            match nexts.len() {
-                1 => frame.push(nexts[0]),
-                3 => {
-                    let nexts = nexts.iter().filter_map(|&k| match Knot::between(prev, k) {
-                        BetweenKnotPair::Diag(v) if area.contains(&v) => Some(k),
-                        _ => None,
-                    });
-                    let next = nexts.clone().next().unwrap();
-                    match nexts.clone().count() {
-                        1 => frame.push(next),
-                        _ => {
-                            dbg!(frame, nexts.collect::<Vec<_>>(), next);
-                            panic!()
-                        }
-                    }
-                }
-                _ => {
-                    dbg!(frame, nexts);
-                    panic!()
-                }
-            }
-            if frame.len() > 15000 {
-                dbg!(frame);
-                panic!()
-            }
+/// Reason why area couldn't be traced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceError {
+    Empty,
+    /// Contour can't be continued from knot.
+    DeadEnd(Knot),
+    /// Another outer contour starts from knot.
+    SeveralFrames(Knot),
+}
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceError::Empty => write!(f, "empty area"),
+            TraceError::DeadEnd(k) => write!(f, "contour can't be continued from {k:?}"),
+            TraceError::SeveralFrames(k) => write!(f, "another outer contour starts at {k:?}"),
         }
-        // dbg!(&frame);
-        frame
     }
 }
 
+impl std::error::Error for TraceError {}
+
 #[derive(derive_new::new, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Knot {
     pub v: V,
 }
 
 #[derive(Debug, PartialEq, Eq)]
-enum BetweenKnotPair {
+pub enum BetweenKnotPair {
     Err,
     Diag(V),
     Straight([V; 2]),
 }
 
 impl Knot {
-    fn from_v(v: V) -> smallvec::SmallVec<[Knot; 4]> {
+    /// Corners of pixel.
+    pub fn from_v(v: V) -> smallvec::SmallVec<[Knot; 4]> {
         let cands = [v, v.r(), v.d(), v.pad(1)];
-        cands.into_iter().map(|c| Knot::new(c)).collect()
+        cands.into_iter().map(Knot::new).collect()
     }
 
-    fn neigh_v(self, filter: &dyn Fn(V) -> bool) -> smallvec::SmallVec<[V; 4]> {
+    /// Pixels around knot.
+    pub fn neigh_v(self, filter: &dyn Fn(V) -> bool) -> smallvec::SmallVec<[V; 4]> {
         let v = self.v;
         [v, v.u(), v.l(), (v.y - 1, v.x - 1).into()]
             .into_iter()
@@ -122,15 +117,8 @@ impl Knot {
             .collect()
     }
 
-    fn neigh(self, filter: &dyn Fn(Knot) -> bool) -> smallvec::SmallVec<[Knot; 4]> {
-        self.v
-            .neigh1f(&|v| filter(Knot { v }))
-            .into_iter()
-            .map(|v| Knot { v })
-            .collect()
-    }
-
-    fn between(a: Knot, b: Knot) -> BetweenKnotPair {
+    /// Pixels separated by segment between knots.
+    pub fn between(a: Knot, b: Knot) -> BetweenKnotPair {
         let v = V::min_common(a.v, b.v);
         match a.v.sq_dist(b.v) {
             1 if a.v.x == b.v.x => BetweenKnotPair::Straight([v, v.l()]),
@@ -180,4 +168,45 @@ mod tests {
             BetweenKnotPair::Diag(V::new(5, 4))
         );
     }
+
+    #[test]
+    fn trace_errors() {
+        assert_eq!(Polygon::from_area(&HashSet::new()), Err(TraceError::Empty));
+        let square = HashSet::from([V::new(5, 5), V::new(5, 6), V::new(6, 5), V::new(6, 6)]);
+        let (frame, holes) = Polygon::from_area(&square).unwrap();
+        assert_eq!(frame.len(), 9);
+        assert!(holes.is_empty());
+    }
+
+    #[test]
+    fn holes() {
+        let mut ring: HashSet<_> = Rect::new((0, 0).into(), (5, 5).into()).iter().collect();
+        for v in Rect::new((1, 1).into(), (3, 3).into()).iter() {
+            ring.remove(&v);
+        }
+        let (frame, holes) = Polygon::from_area(&ring).unwrap();
+        assert_eq!(Polygon::area(&frame), 2 * 25);
+        assert_eq!(holes.len(), 1);
+        assert_eq!(Polygon::area(&holes[0]), -2 * 9);
+
+        ring.insert(V::new(3, 3));
+        let (_, holes) = Polygon::from_area(&ring).unwrap();
+        assert_eq!(holes.len(), 1);
+        assert_eq!(Polygon::area(&holes[0]), -2 * 8);
+        assert_eq!(holes[0].first(), holes[0].last());
+
+        // Pixels touching by corner aren't linked, so there is no hole.
+        let mut notched: HashSet<_> = Rect::new0((3, 3).into()).iter().collect();
+        notched.remove(&V::new(1, 1));
+        notched.remove(&V::new(2, 2));
+        let (frame, holes) = Polygon::from_area(&notched).unwrap();
+        assert_eq!(Polygon::area(&frame), 2 * 7);
+        assert!(holes.is_empty());
+
+        let diag = HashSet::from([V::new(0, 0), V::new(1, 1)]);
+        assert!(matches!(
+            Polygon::from_area(&diag),
+            Err(TraceError::SeveralFrames(_))
+        ));
+    }
 }
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use super::{Color, Image};
+
+/// Reduction of colors of image before partitioning.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quantize {
+    /// Size of built palette, ignored if `palette` is given.
+    pub colors: usize,
+    pub method: Method,
+    /// Fixed (non empty) palette to map colors onto.
+    #[serde(deserialize_with = "non_empty")]
+    pub palette: Option<Vec<Color>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Method {
+    #[default]
+    MedianCut,
+    /// Refines median cut palette by k-means.
+    KMeans { iterations: usize },
+}
+
+impl Default for Quantize {
+    fn default() -> Self {
+        Self {
+            colors: 16,
+            method: Method::MedianCut,
+            palette: None,
+        }
+    }
+}
+
+impl Quantize {
+    pub fn palette(&self, img: &Image) -> Palette {
+        if let Some(colors) = &self.palette {
+            return Palette::new(colors.clone());
+        }
+        match self.method {
+            Method::MedianCut => Palette::median_cut(img, self.colors),
+            Method::KMeans { iterations } => Palette::k_means(img, self.colors, iterations),
+        }
+    }
+
+    pub fn apply(&self, img: &Image) -> Image {
+        self.palette(img).apply(img)
+    }
+}
+
+/// Deserializes optional palette, rejecting empty one.
+pub(crate) fn non_empty<'de, D: serde::Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Option<Vec<Color>>, D::Error> {
+    let palette: Option<Vec<Color>> = serde::Deserialize::deserialize(d)?;
+    match palette {
+        Some(p) if p.is_empty() => Err(serde::de::Error::custom("empty palette")),
+        p => Ok(p),
+    }
+}
+
+#[derive(derive_new::new, Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: Vec<Color>,
+}
+
+impl Palette {
+    /// Hex colors (see `Color::from_str`) separated by whitespace.
+    pub fn parse(s: &str) -> Result<Self> {
+        let colors: Vec<_> = s
+            .split_whitespace()
+            .map(|c| c.parse())
+            .collect::<Result<_>>()?;
+        anyhow::ensure!(!colors.is_empty(), "empty palette");
+        Ok(Self::new(colors))
+    }
+
+    /// Index of the closest color (by euclidean distance in rgba), `None` for empty palette.
+    pub fn nearest(&self, c: Color) -> Option<usize> {
+        let dist = |p: &Color| -> u32 {
+            let (a, b) = (c.rgba(), p.rgba());
+            (0..4).map(|i| (a[i].abs_diff(b[i]) as u32).pow(2)).sum()
+        };
+        (self.colors.iter().enumerate())
+            .min_by_key(|(_, p)| dist(p))
+            .map(|(i, _)| i)
+    }
+
+    /// Pixels of view are replaced with the closest colors, kept as is by empty palette.
+    pub fn apply(&self, img: &Image) -> Image {
+        let mut r = img.clone();
+        let mut cache = HashMap::new();
+        for v in img.view().iter() {
+            let c = img[v];
+            r[v] = *cache
+                .entry(c)
+                .or_insert_with(|| self.nearest(c).map_or(c, |i| self.colors[i]));
+        }
+        r
+    }
+
+    /// Splits box of colors with the largest channel range at its median until there are `n` boxes.
+    pub fn median_cut(img: &Image, n: usize) -> Self {
+        let boxes = median_cut(histogram(img), n);
+        Self::new(boxes.iter().map(|b| mean(b)).collect())
+    }
+
+    /// Starts with `median_cut` palette.
+    pub fn k_means(img: &Image, n: usize, iterations: usize) -> Self {
+        let hist = histogram(img);
+        let mut palette = Self::median_cut(img, n);
+        for _ in 0..iterations {
+            let mut clusters = vec![vec![]; palette.colors.len()];
+            for &(c, count) in &hist {
+                if let Some(i) = palette.nearest(c) {
+                    clusters[i].push((c, count));
+                }
+            }
+            let colors: Vec<_> = (clusters.iter().zip(&palette.colors))
+                .map(|(cluster, &old)| match cluster.is_empty() {
+                    true => old,
+                    false => mean(cluster),
+                })
+                .collect();
+            if colors == palette.colors {
+                break;
+            }
+            palette.colors = colors;
+        }
+        palette
+    }
+}
+
+/// Distinct colors of view with number of pixels, sorted by color.
+fn histogram(img: &Image) -> Vec<(Color, usize)> {
+    let mut counts = HashMap::new();
+    for (_, c) in img.iter() {
+        *counts.entry(c).or_insert(0) += 1;
+    }
+    let mut r: Vec<_> = counts.into_iter().collect();
+    r.sort_by_key(|(c, _)| c.rgba());
+    r
+}
+
+fn median_cut(hist: Vec<(Color, usize)>, n: usize) -> Vec<Vec<(Color, usize)>> {
+    let range = |b: &[(Color, usize)], ch: usize| {
+        let values = b.iter().map(|(c, _)| c.rgba()[ch]);
+        values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+    };
+    let mut boxes = vec![hist];
+    while boxes.len() < n {
+        let widest = (boxes.iter().enumerate())
+            .filter(|(_, b)| b.len() > 1)
+            .flat_map(|(i, b)| (0..4).map(move |ch| (range(b, ch), i, ch)))
+            .max_by_key(|&(r, i, ch)| (r, std::cmp::Reverse((i, ch))));
+        let Some((_, i, ch)) = widest else { break };
+
+        let b = &mut boxes[i];
+        b.sort_by_key(|(c, _)| c.rgba()[ch]);
+        let half = b.iter().map(|(_, n)| n).sum::<usize>() / 2;
+        let mut count = 0;
+        let median = b.iter().take_while(|(_, n)| {
+            count += n;
+            count <= half
+        });
+        let at = median.count().clamp(1, b.len() - 1);
+        let rest = b.split_off(at);
+        boxes.push(rest);
+    }
+    boxes
+}
+
+fn mean(colors: &[(Color, usize)]) -> Color {
+    let total: usize = colors.iter().map(|(_, n)| n).sum();
+    let mut sum = [0; 4];
+    for &(c, n) in colors {
+        for (s, ch) in sum.iter_mut().zip(c.rgba()) {
+            *s += ch as usize * n;
+        }
+    }
+    Color::new(sum.map(|s| ((s + total / 2) / total) as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::equivalance::EqKind;
+    use crate::core::V;
+
+    #[test]
+    fn quantize() {
+        // Noisy red and blue halves.
+        let mut img = Image::new(V::new(8, 8), 1);
+        for v in img.view().iter() {
+            let noise = ((v.y * 7 + v.x * 3) % 5) as u8;
+            img[v] = match v.x < 5 {
+                true => Color::new([200 + noise, noise, 10, 255]),
+                false => Color::new([noise, 20, 180 + noise, 255]),
+            };
+        }
+
+        for method in [Method::MedianCut, Method::KMeans { iterations: 5 }] {
+            let q = Quantize {
+                colors: 2,
+                method,
+                palette: None,
+            };
+            let img = q.apply(&img);
+            let mut colors: Vec<_> = img.iter().map(|(_, c)| c.rgba()).collect();
+            colors.sort();
+            colors.dedup();
+            assert_eq!(colors.len(), 2, "{method:?}");
+            assert_ne!(img[V::new(1, 1)], img[V::new(1, 8)], "{method:?}");
+            assert_eq!(img[V::new(0, 0)], Color::default(), "padding is kept");
+        }
+
+        let palette = Palette::parse("#ff0000 0000ff\n#00ff0080").unwrap();
+        assert_eq!(palette.colors[2], Color::new([0, 255, 0, 128]));
+        let img = palette.apply(&img);
+        assert_eq!(img[V::new(1, 1)], Color::new([255, 0, 0, 255]));
+        assert_eq!(img[V::new(1, 8)], Color::new([0, 0, 255, 255]));
+        assert!(Palette::parse("#ff00").is_err());
+        assert!(Palette::parse(" ").is_err());
+        assert_eq!(Palette::new(vec![]).apply(&img).pixels(), img.pixels());
+
+        let q: Result<Quantize, _> = toml::from_str("palette = []");
+        assert!(q.is_err());
+        let eq: Result<EqKind, _> = toml::from_str("kind = 'palette_index'\npalette = []");
+        assert!(eq.is_err());
+        let eq: EqKind = toml::from_str("kind = 'palette_index'").unwrap();
+        assert_eq!(eq, EqKind::PaletteIndex { palette: None });
+    }
+}
@@ -0,0 +1,232 @@
+use super::{Color, Curve, Image, Point, Segment, Shape, V};
+
+/// Scanline rasterizer: every pixel row is sampled by `samples` sub-scanlines,
+/// and horizontal coverage of every sub-scanline is exact.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rasterizer {
+    pub samples: usize,
+    /// Largest distance (in pixels) of flattened cubic from the original.
+    pub tolerance: f64,
+}
+
+impl Default for Rasterizer {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            tolerance: 0.01,
+        }
+    }
+}
+
+/// Line of flattened outline, `from.y < to.y`, `dir` is winding direction.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    from: Point,
+    to: Point,
+    dir: i32,
+}
+
+impl Line {
+    fn x_at(&self, y: f64) -> f64 {
+        let t = (y - self.from.y) / (self.to.y - self.from.y);
+        self.from.x + (self.to.x - self.from.x) * t
+    }
+}
+
+impl Rasterizer {
+    /// Transparent image of `size` (without padding) with `shapes` drawn in order.
+    pub fn render<'a>(&self, size: V, shapes: impl IntoIterator<Item = &'a Shape>) -> Image {
+        let mut img = Image::new(size, 0);
+        for shape in shapes {
+            self.fill(&mut img, shape);
+        }
+        img
+    }
+
+    /// Draws `shape` over `img`, coordinates are taken as in the whole buffer (padding included).
+    /// Like svg output, fill rule is `evenodd` for shapes with holes and `nonzero` otherwise.
+    pub fn fill(&self, img: &mut Image, shape: &Shape) {
+        let mut lines = vec![];
+        for curve in std::iter::once(&shape.frame).chain(&shape.holes) {
+            self.flatten(curve, &mut lines);
+        }
+        let evenodd = !shape.holes.is_empty();
+        let size = img.size();
+        let (Some(top), Some(bottom)) = (
+            lines.iter().map(|l| l.from.y).min_by(f64::total_cmp),
+            lines.iter().map(|l| l.to.y).max_by(f64::total_cmp),
+        ) else {
+            return;
+        };
+        lines.sort_by(|a, b| a.from.y.total_cmp(&b.from.y));
+
+        let top = top.floor().max(0.) as usize;
+        let bottom = (bottom.ceil().max(0.) as usize).min(size.y);
+        let weight = 1. / self.samples as f64;
+        let mut coverage = vec![0f64; size.x + 1];
+        let (mut next, mut active): (usize, Vec<Line>) = (0, vec![]);
+        let mut crossings: Vec<(f64, i32)> = vec![];
+        for row in top..bottom {
+            coverage.iter_mut().for_each(|c| *c = 0.);
+            for s in 0..self.samples {
+                let y = row as f64 + (s as f64 + 0.5) * weight;
+                while next < lines.len() && lines[next].from.y <= y {
+                    active.push(lines[next]);
+                    next += 1;
+                }
+                active.retain(|l| l.to.y > y);
+
+                crossings.clear();
+                crossings
+                    .extend((active.iter().filter(|l| l.from.y <= y)).map(|l| (l.x_at(y), l.dir)));
+                crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let mut winding = 0;
+                for w in crossings.windows(2) {
+                    winding += w[0].1;
+                    let inside = match evenodd {
+                        true => winding % 2 != 0,
+                        false => winding != 0,
+                    };
+                    if inside {
+                        add_span(&mut coverage, w[0].0, w[1].0, weight);
+                    }
+                }
+            }
+            for (x, &c) in coverage.iter().take(size.x).enumerate() {
+                if c > 0. {
+                    let v = V::new(row, x);
+                    img[v] = blend(img[v], shape.color, c.min(1.));
+                }
+            }
+        }
+    }
+
+    /// Appends non horizontal lines of closed `curve`.
+    fn flatten(&self, curve: &Curve, lines: &mut Vec<Line>) {
+        let mut push = |from: Point, to: Point| match from.y.total_cmp(&to.y) {
+            std::cmp::Ordering::Less => lines.push(Line { from, to, dir: 1 }),
+            std::cmp::Ordering::Greater => lines.push(Line {
+                from: to,
+                to: from,
+                dir: -1,
+            }),
+            std::cmp::Ordering::Equal => {}
+        };
+        let mut from = curve.start;
+        for &s in &curve.segments {
+            match s {
+                Segment::Line(to) => push(from, to),
+                Segment::Cubic(c1, c2, to) => {
+                    // Uniform subdivision, second differences bound the deviation.
+                    let dd = ((from - c1 * 2. + c2).len()).max((c1 - c2 * 2. + to).len());
+                    let n = ((0.75 * dd / self.tolerance).sqrt().ceil() as usize).clamp(1, 256);
+                    let mut prev = from;
+                    for i in 1..=n {
+                        let p = cubic(from, c1, c2, to, i as f64 / n as f64);
+                        push(prev, p);
+                        prev = p;
+                    }
+                }
+            }
+            from = s.end();
+        }
+        // Unclosed curve is closed by line.
+        push(from, curve.start);
+    }
+}
+
+fn cubic(p0: Point, p1: Point, p2: Point, p3: Point, t: f64) -> Point {
+    let u = 1. - t;
+    p0 * (u * u * u) + p1 * (3. * u * u * t) + p2 * (3. * u * t * t) + p3 * (t * t * t)
+}
+
+/// Adds `weight` times covered part of every pixel of `[from, to)`.
+fn add_span(coverage: &mut [f64], from: f64, to: f64, weight: f64) {
+    let width = (coverage.len() - 1) as f64;
+    let (from, to) = (from.clamp(0., width), to.clamp(0., width));
+    if from >= to {
+        return;
+    }
+    let (first, last) = (from.floor() as usize, to.floor() as usize);
+    if first == last {
+        coverage[first] += (to - from) * weight;
+        return;
+    }
+    coverage[first] += (first as f64 + 1. - from) * weight;
+    for c in &mut coverage[first + 1..last] {
+        *c += weight;
+    }
+    coverage[last] += (to - last as f64) * weight;
+}
+
+/// Source-over compositing of `src` with `coverage` onto `dst`, non premultiplied.
+fn blend(dst: Color, src: Color, coverage: f64) -> Color {
+    let (d, s) = (dst.rgba(), src.rgba());
+    let sa = s[3] as f64 / 255. * coverage;
+    let da = d[3] as f64 / 255.;
+    let a = sa + da * (1. - sa);
+    if a <= 0. {
+        return dst;
+    }
+    let channel = |i: usize| {
+        let c = (s[i] as f64 * sa + d[i] as f64 * da * (1. - sa)) / a;
+        c.round() as u8
+    };
+    Color::new([channel(0), channel(1), channel(2), (a * 255.).round() as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rasterize() {
+        let red = Color::new([255, 0, 0, 255]);
+        let square = |from: f64, to: f64| Curve {
+            start: Point::new(from, from),
+            segments: [(from, to), (to, to), (to, from), (from, from)]
+                .map(|(y, x)| Segment::Line(Point::new(y, x)))
+                .to_vec(),
+        };
+        // Square 1..7 with hole 3..5, the left side is slanted to `x = 0` at the top.
+        let mut frame = square(1., 7.);
+        frame.segments[3] = Segment::Line(Point::new(1., 0.));
+        let shape = Shape {
+            frame,
+            holes: vec![square(3., 5.).reversed()],
+            color: red,
+        };
+        let img = Rasterizer::default().render(V::new(8, 8), [&shape]);
+
+        assert_eq!(img[V::new(0, 0)], Color::default());
+        assert_eq!(img[V::new(2, 2)], red);
+        assert_eq!(img[V::new(3, 3)], Color::default(), "hole");
+        assert_eq!(img[V::new(6, 6)], red);
+        assert_eq!(img[V::new(7, 7)], Color::default());
+        // Slanted side crosses row 4 from `x = 1/2` to `x = 2/3`.
+        let c = img[V::new(4, 0)];
+        assert_eq!(c.rgb(), [255, 0, 0]);
+        assert!((100..=112).contains(&c.alpha()), "{c:?}");
+
+        // Circle approximation by four cubics.
+        let k = 0.5523 * 3.;
+        let p = |y, x| Point::new(4. + y, 4. + x);
+        let circle = Shape {
+            frame: Curve {
+                start: p(-3., 0.),
+                segments: vec![
+                    Segment::Cubic(p(-3., k), p(-k, 3.), p(0., 3.)),
+                    Segment::Cubic(p(k, 3.), p(3., k), p(3., 0.)),
+                    Segment::Cubic(p(3., -k), p(k, -3.), p(0., -3.)),
+                    Segment::Cubic(p(-k, -3.), p(-3., -k), p(-3., 0.)),
+                ],
+            },
+            holes: vec![],
+            color: red,
+        };
+        let img = Rasterizer::default().render(V::new(8, 8), [&circle]);
+        let area: f64 = img.iter().map(|(_, c)| c.alpha() as f64 / 255.).sum();
+        assert!((area - std::f64::consts::PI * 9.).abs() < 0.2, "{area}");
+        assert_eq!(img[V::new(3, 3)], red);
+    }
+}
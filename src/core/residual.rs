@@ -0,0 +1,63 @@
+use super::partition::{DisjointSets, NONE};
+use super::{Image, Partition, V};
+
+impl Partition {
+    /// Areas of pixels of `img` differing from `render` by more than `diff` (L1),
+    /// neighbours are linked if their colors in `img` differ by `diff` or less.
+    /// Expects `render` to cover the whole buffer of `img`, as `Rasterizer::render` does.
+    pub fn residual(img: &Image, render: &Image, diff: u16) -> Self {
+        let view = img.view();
+        let index = |v: V| (v.y - view.from.y) * view.size.x + (v.x - view.from.x);
+        let significant: Vec<_> = view
+            .iter()
+            .map(|v| img[v].diff_l1(render[v]) > diff)
+            .collect();
+
+        let mut sets = DisjointSets::new(significant.len());
+        for v in view.iter().filter(|&v| significant[index(v)]) {
+            for n in [v.r(), v.d()] {
+                let linked = img.contains(n) && significant[index(n)];
+                if linked && img[v].diff_l1(img[n]) <= diff {
+                    sets.union(index(v), index(n));
+                }
+            }
+        }
+        let labels = (0..significant.len())
+            .map(|i| match significant[i] {
+                true => sets.find(i) as u32,
+                false => NONE,
+            })
+            .collect();
+        Self::from_labels(view, labels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Color;
+
+    #[test]
+    fn residual() {
+        // Render misses a red spot and a green stripe on gray.
+        let mut img = Image::new(V::new(6, 6), 1);
+        for v in img.view().iter() {
+            img[v] = Color::new([100, 100, 100, 255]);
+        }
+        let render = img.crop(V::new(0, 0), img.size());
+        img[V::new(2, 2)] = Color::new([255, 0, 0, 255]);
+        for y in 1..=6 {
+            img[V::new(y, 5)] = Color::new([0, 255, 0, 255]);
+        }
+        // Small difference is not significant.
+        img[V::new(6, 1)] = Color::new([110, 100, 100, 255]);
+
+        let pn = Partition::residual(&img, &render, 30);
+        pn.check();
+        assert_eq!(pn.areas.len(), 2);
+        assert_eq!(pn.area(V::new(2, 2)).map(|a| pn.areas[a].len()), Some(1));
+        assert_eq!(pn.area(V::new(1, 5)).map(|a| pn.areas[a].len()), Some(6));
+        assert_eq!(pn.area(V::new(6, 1)), None);
+        assert_eq!(pn.area(V::new(1, 1)), None);
+    }
+}
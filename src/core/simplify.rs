@@ -0,0 +1,145 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::curve::dist_to_line;
+use super::svg::SVG;
+use super::{Knot, Point};
+
+/// Reduces number of knots in chain, first and last knots are kept in place.
+///
+/// Closed chains are not reduced below a quadrilateral.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Simplifier {
+    /// Only knots in the middle of straight runs are removed.
+    #[default]
+    Collinear,
+    /// Result stays within `tolerance` pixels of the chain.
+    DouglasPeucker { tolerance: f64 },
+    /// Knots forming triangles with area below `tolerance`² are removed, smallest first.
+    Visvalingam { tolerance: f64 },
+}
+
+impl Simplifier {
+    pub fn apply(&self, knots: &[Knot]) -> Vec<Knot> {
+        match *self {
+            Simplifier::Collinear => SVG::simplify_path(knots),
+            Simplifier::DouglasPeucker { tolerance } => douglas_peucker(knots, tolerance),
+            Simplifier::Visvalingam { tolerance } => visvalingam(knots, tolerance),
+        }
+    }
+}
+
+fn closed(knots: &[Knot]) -> bool {
+    knots.len() > 1 && knots[0] == knots[knots.len() - 1]
+}
+
+fn douglas_peucker(knots: &[Knot], tolerance: f64) -> Vec<Knot> {
+    let n = knots.len();
+    let pts: Vec<Point> = knots.iter().map(|&k| k.into()).collect();
+    let mut keep = vec![true; n];
+    keep[1..n.saturating_sub(1)].fill(false);
+
+    // Closed chain is split at the farthest knot, then each half keeps at least one more.
+    let force: usize = if closed(knots) { 2 } else { 0 };
+    let mut stack = vec![(0, n.saturating_sub(1), force)];
+    while let Some((a, b, force)) = stack.pop() {
+        let far = (a + 1..b)
+            .map(|i| (dist_to_line(pts[i], pts[a], pts[b]), i))
+            .max_by(|l, r| l.0.total_cmp(&r.0));
+        match far {
+            Some((d, i)) if force > 0 || d > tolerance => {
+                keep[i] = true;
+                stack.push((a, i, force.saturating_sub(1)));
+                stack.push((i, b, force.saturating_sub(1)));
+            }
+            _ => {}
+        }
+    }
+
+    (0..n).filter(|&i| keep[i]).map(|i| knots[i]).collect()
+}
+
+fn visvalingam(knots: &[Knot], tolerance: f64) -> Vec<Knot> {
+    let n = knots.len();
+    if n < 3 {
+        return knots.to_vec();
+    }
+    let min = if closed(knots) { 5 } else { 2 };
+    // Doubled area is integer for integer knots.
+    let threshold = 2. * tolerance * tolerance;
+    let doubled = |a: usize, b: usize, c: usize| {
+        let (a, b, c) = (knots[a].v, knots[b].v, knots[c].v);
+        let (y1, x1) = (b.y as isize - a.y as isize, b.x as isize - a.x as isize);
+        let (y2, x2) = (c.y as isize - a.y as isize, c.x as isize - a.x as isize);
+        (x1 * y2 - y1 * x2).unsigned_abs()
+    };
+
+    let mut prev: Vec<usize> = (0..n).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (1..=n).collect();
+    let mut alive = vec![true; n];
+    let mut heap: BinaryHeap<_> = (1..n - 1)
+        .map(|i| Reverse((doubled(i - 1, i, i + 1), i)))
+        .collect();
+    let mut left = n;
+    while let Some(Reverse((area, i))) = heap.pop() {
+        // Entries are not removed from heap, so outdated ones are skipped.
+        if !alive[i] || area != doubled(prev[i], i, next[i]) {
+            continue;
+        }
+        if area as f64 >= threshold || left <= min {
+            break;
+        }
+        alive[i] = false;
+        left -= 1;
+        let (p, q) = (prev[i], next[i]);
+        next[p] = q;
+        prev[q] = p;
+        for j in [p, q].into_iter().filter(|&j| j != 0 && j != n - 1) {
+            heap.push(Reverse((doubled(prev[j], j, next[j]), j)));
+        }
+    }
+
+    (0..n).filter(|&i| alive[i]).map(|i| knots[i]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::V;
+
+    #[test]
+    fn simplifiers() {
+        // Staircase with steps of one pixel, then long straight run.
+        let mut knots: Vec<_> = (0..20)
+            .map(|i: usize| Knot::new(V::new(i / 2, i.div_ceil(2))))
+            .collect();
+        knots.extend((11..20).map(|x| Knot::new(V::new(10, x))));
+        let (first, last) = (knots[0], knots[knots.len() - 1]);
+
+        for s in [
+            Simplifier::DouglasPeucker { tolerance: 1. },
+            Simplifier::Visvalingam { tolerance: 1. },
+        ] {
+            let r = s.apply(&knots);
+            assert_eq!((r[0], r[r.len() - 1]), (first, last), "{s:?}");
+            assert!(r.len() <= 4, "{s:?}: {r:?}");
+            let pts: Vec<Point> = r.iter().map(|&k| k.into()).collect();
+            for k in &knots {
+                let d = (pts.windows(2))
+                    .map(|w| dist_to_line((*k).into(), w[0], w[1]))
+                    .fold(f64::MAX, f64::min);
+                assert!(d <= 1., "{s:?}: {k:?} is {d} away");
+            }
+        }
+
+        // Closed square doesn't collapse.
+        let square = [(0, 0), (0, 2), (2, 2), (2, 0), (0, 0)].map(|(y, x)| Knot::new(V::new(y, x)));
+        for s in [
+            Simplifier::DouglasPeucker { tolerance: 10. },
+            Simplifier::Visvalingam { tolerance: 10. },
+        ] {
+            assert_eq!(s.apply(&square), square, "{s:?}");
+        }
+    }
+}
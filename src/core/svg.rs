@@ -1,8 +1,8 @@
-use svg::node::element::{path::Data, Path};
-use svg::Document;
+use svg::node::element::{path::Data, Group, Path};
+use svg::{Document, Node};
 
 use super::polygon::Knot;
-use super::{Polygon, V};
+use super::{Hierarchy, Point, Polygon, Segment, Shape, V};
 
 pub struct SVG {
     pub doc: Document,
@@ -15,32 +15,101 @@ impl SVG {
         SVG { doc }
     }
 
-    pub fn extend_with_polygons(&mut self, pgs: &Vec<Polygon>) {
+    pub fn extend_with_polygons(&mut self, pgs: &[Polygon]) {
+        let shapes: Vec<Shape> = pgs.iter().map(Shape::from).collect();
+        self.extend_with_shapes(&shapes)
+    }
+
+    /// Holes are emitted as subpaths of the same path with `evenodd` fill rule.
+    pub fn extend_with_shapes(&mut self, shapes: &[Shape]) {
         let mut doc = self.doc.clone();
-        for pg in pgs {
-            let color = pg.color.format_svg();
-            let mut data = Data::new();
-            let path = Self::simplify_path(&pg.frame);
-            data = data.move_to((path[0].v.x, path[0].v.y));
-            for p in &path[1..] {
-                data = data.line_to((p.v.x, p.v.y));
+        for shape in shapes {
+            doc = doc.add(Self::path(shape));
+        }
+        self.doc = doc
+    }
+
+    /// Shapes of leaves (`None` for skipped ones) are nested in groups by merges of `tree`.
+    pub fn extend_with_hierarchy(&mut self, tree: &Hierarchy, shapes: &[Option<Shape>]) {
+        let mut nodes: Vec<Option<Box<dyn Node>>> = (shapes.iter().enumerate())
+            .map(|(n, s)| {
+                let path = |s| Box::new(Self::path(s).set("id", format!("n{n}"))) as Box<dyn Node>;
+                s.as_ref().map(path)
+            })
+            .collect();
+        for m in &tree.merges {
+            let mut group = Group::new()
+                .set("id", format!("n{}", nodes.len()))
+                .set("data-level", (m.level * 100.).round() / 100.);
+            let children: Vec<_> = m.children.iter().filter_map(|&c| nodes[c].take()).collect();
+            let empty = children.is_empty();
+            for node in children {
+                group = group.add(node);
             }
-            data = data.close();
-            let part = Path::new()
-                .set("fill", color)
-                .set("stroke", "none")
-                .set("d", data);
-            doc = doc.add(part);
+            nodes.push((!empty).then(|| Box::new(group) as Box<dyn Node>));
+        }
+        let mut doc = self.doc.clone();
+        for node in nodes.into_iter().flatten() {
+            doc = doc.add(node);
         }
         self.doc = doc
     }
 
-    pub fn simplify_path(path: &Vec<Knot>) -> Vec<Knot> {
+    fn path(shape: &Shape) -> Path {
+        let mut data = Data::new();
+        for curve in std::iter::once(&shape.frame).chain(&shape.holes) {
+            data = data.move_to(Self::xy(curve.start));
+            let mut from = curve.start;
+            for &s in &curve.segments {
+                data = match (s, Self::quadratic(from, s)) {
+                    (Segment::Line(p), _) => data.line_to(Self::xy(p)),
+                    (Segment::Cubic(.., p), Some(q)) => {
+                        data.quadratic_curve_to((Self::xy(q), Self::xy(p)))
+                    }
+                    (Segment::Cubic(c1, c2, p), None) => {
+                        data.cubic_curve_to((Self::xy(c1), Self::xy(c2), Self::xy(p)))
+                    }
+                };
+                from = s.end();
+            }
+            data = data.close();
+        }
+        let mut path = Path::new()
+            .set("fill", shape.color.format_svg())
+            .set("stroke", "none")
+            .set("d", data);
+        if !shape.holes.is_empty() {
+            path = path.set("fill-rule", "evenodd");
+        }
+        if let Some(opacity) = shape.color.format_svg_opacity() {
+            path = path.set("fill-opacity", opacity);
+        }
+        path
+    }
+
+    /// Coordinates rounded to hundredths.
+    fn xy(p: Point) -> (f32, f32) {
+        let round = |c: f64| ((c * 100.).round() / 100.) as f32;
+        (round(p.x), round(p.y))
+    }
+
+    /// Control point if cubic is elevated quadratic.
+    fn quadratic(from: Point, s: Segment) -> Option<Point> {
+        match s {
+            Segment::Cubic(c1, c2, to) => {
+                let (q1, q2) = ((c1 * 3. - from) * 0.5, (c2 * 3. - to) * 0.5);
+                (q1.dist(q2) < 1e-3).then_some(q1)
+            }
+            Segment::Line(_) => None,
+        }
+    }
+
+    pub fn simplify_path(path: &[Knot]) -> Vec<Knot> {
         let mut good = vec![true; path.len()];
 
         // Simplify straight lines.
         for i in 2..good.len() {
-            let (a, b, c) = (path[i - 2], path[i - 1], path[i - 0]);
+            let (a, b, c) = (path[i - 2], path[i - 1], path[i]);
             let dx1 = b.v.x as isize - a.v.x as isize;
             let dy1 = b.v.y as isize - a.v.y as isize;
             let dx2 = c.v.x as isize - b.v.x as isize;
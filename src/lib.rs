@@ -0,0 +1,11 @@
+pub mod core;
+pub mod utility;
+
+mod convert;
+mod refine;
+
+pub use convert::{
+    convert, convert_with, hierarchy_svg, Improvement, NodeCount, Observer, Options, Quality, Tier,
+    VectorDocument,
+};
+pub use refine::Refine;
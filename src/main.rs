@@ -1,17 +1,67 @@
+mod batch;
 mod config;
 mod pipeline;
-
-mod core;
-mod utility;
+mod profile;
 
 use clap::Parser as _;
 
+#[global_allocator]
+static ALLOCATOR: profile::Counting = profile::Counting::new();
+
 fn main() {
     let config = config::Config::parse();
-    let timer_all = config.timer("all");
+    if config.profile.is_some() {
+        ALLOCATOR.enable();
+    }
+    let profiler = profile::Profiler::new(config.timers);
+    let all = profiler.start("all");
 
-    let mut pipeline = pipeline::Pipeline::new(config);
-    pipeline.run("icons");
+    let description = match config.description() {
+        Ok(description) => description,
+        Err(e) => {
+            eprintln!("pipeline: {e:#}");
+            std::process::exit(1)
+        }
+    };
+    if let Err(e) = config.check_outputs() {
+        eprintln!("inputs: {e:#}");
+        std::process::exit(1)
+    }
+    let profile = config
+        .profile
+        .clone()
+        .map(|path| (path, config.profile_format));
+    let mut failed = false;
+    if config.batch {
+        match batch::Batch::new(&config, &description.options).run(&all) {
+            Ok(summary) => {
+                summary.print();
+                failed = summary.failed();
+            }
+            Err(e) => {
+                eprintln!("batch: {e:#}");
+                failed = true;
+            }
+        }
+    } else {
+        let inputs = config.inputs.clone();
+        let mut pipeline = pipeline::Pipeline::new(config, description);
+        for input in inputs {
+            if let Err(e) = pipeline.run(&input, &all) {
+                eprintln!("{}: {e:#}", input.display());
+                failed = true;
+            }
+        }
+    }
 
-    timer_all.stop()
+    all.stop();
+    if let Some((path, format)) = profile {
+        if let Err(e) = profiler.write(&path, format) {
+            eprintln!("profile: {e:#}");
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1)
+    }
 }
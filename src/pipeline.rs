@@ -1,88 +1,193 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::config::Config;
+use anyhow::Result;
 
-use crate::core::equivalance::*;
-use crate::core::svg::SVG;
-use crate::core::Polygon;
-use crate::core::{Image, Partition};
+use rast2vec::core::{Hierarchy, Image, Partition};
+use rast2vec::{hierarchy_svg, Improvement, Observer, Options, VectorDocument};
+
+use crate::config::{Config, Description};
+use crate::profile::{Stage, Stages};
 
 #[derive(derive_new::new)]
 pub struct Pipeline {
     config: Config,
+    description: Description,
     #[new(default)]
     counter: usize,
 }
 
 impl Pipeline {
-    pub fn run(&mut self, name: &str) {
-        let name = format!("{name}.png");
-        let img = self.load(&name);
-
-        // let img = img.crop(V::new(0, 500), V::new(50, 50));
-        let img = img.pad(10);
-        img.store(&self.path_w(&name)).unwrap();
-
-        // let pn_huge = self.try_part(&img, &mut L1DiffSup::new(4, 10), (250, None));
-        let pn_large = self.try_part(&img, &mut L1DiffSup::new(4, 10), (18000, None));
-        let pn_medium = self.try_part(&img, &mut L1DiffSup::new(2, 20), (500, Some(18000)));
-        let pn_small = self.try_part(&img, &mut L1DiffSup::new(2, 25), (5, Some(500)));
-
-        let pg_large = Polygon::from_partition(&pn_large, &img);
-        let pg_medium = Polygon::from_partition(&pn_medium, &img);
-        let pg_small = Polygon::from_partition(&pn_small, &img);
-
-        let mut r = SVG::new(img.size());
-        r.extend_with_polygons(&pg_large);
-        let mut out = std::fs::File::create(&self.path_w("r1.svg")).unwrap();
-        svg::write(&mut out, &r.doc).unwrap();
-        r.extend_with_polygons(&pg_medium);
-        let mut out = std::fs::File::create(&self.path_w("r2.svg")).unwrap();
-        svg::write(&mut out, &r.doc).unwrap();
-        r.extend_with_polygons(&pg_small);
-        let mut out = std::fs::File::create(&self.path_w("r3.svg")).unwrap();
-        svg::write(&mut out, &r.doc).unwrap();
+    pub fn run(&mut self, input: &Path, parent: &Stage) -> Result<()> {
+        let stage = parent.start(input.display());
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        let t = stage.start("load");
+        let source = Image::load(&self.config.source(input), 0)?;
+        t.count("pixels", pixels(&source));
+        t.stop();
+
+        let mut outputs = SideOutputs {
+            config: &self.config,
+            options: &self.description.options,
+            counter: &mut self.counter,
+            stem: &stem,
+            stages: Stages::new(&stage),
+            improvement: None,
+        };
+        let doc = rast2vec::convert_with(&source, &self.description.options, &mut outputs)?;
+        if let (true, Some(improvement)) = (self.config.report, outputs.improvement) {
+            if let Some(passes) = improvement.passes {
+                eprintln!("{}: {passes} refinement passes", input.display());
+            }
+            if let Some((before, after)) = improvement.error {
+                eprintln!(
+                    "{}: optimized error {before:.6} -> {after:.6}",
+                    input.display()
+                );
+            }
+        }
+        for e in &doc.errors {
+            eprintln!("{}: skipped area: {e}", input.display());
+        }
+        if self.config.report {
+            self.report(input, &doc);
+        }
+        if self.config.render {
+            let t = stage.start("render");
+            let render = doc.render();
+            t.count("pixels", pixels(&render));
+            t.stop();
+            render.store(&self.path_w(&format!("{stem}_render.png")))?;
+        }
+        let t = stage.start("save");
+        for output in &self.description.outputs {
+            let path = self.path_w(&format!("{stem}_{}.svg", output.name));
+            svg::save(path, &doc.svg_layers(output.layers).doc)?;
+        }
+        let output = self.config.output(input);
+        if let Some(dir) = output.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        doc.save(&output)?;
+        t.stop();
+        if self.config.quality {
+            let t = stage.start("quality");
+            let quality = doc.save_quality(&source, &output)?;
+            t.stop();
+            let m = quality.metrics;
+            eprintln!(
+                "{}: psnr {:.2}, ssim {:.4}, {} paths, {} nodes, {} bytes",
+                input.display(),
+                m.psnr,
+                m.ssim,
+                quality.paths,
+                quality.nodes,
+                quality.svg_bytes
+            );
+        }
+        Ok(())
     }
 }
 
 impl Pipeline {
-    fn try_part(
-        &mut self,
-        img: &Image,
-        eq: &mut impl Equivalance,
-        range: (usize, Option<usize>),
-    ) -> Partition {
+    fn report(&self, input: &Path, doc: &VectorDocument) {
+        for (layer, nodes) in doc.nodes.iter().enumerate() {
+            for (shape, n) in nodes.iter().enumerate() {
+                eprintln!(
+                    "{}: layer {layer} shape {shape}: {} {} nodes",
+                    input.display(),
+                    change(n.removed()),
+                    n.traced
+                );
+            }
+            let removed: isize = nodes.iter().map(|n| n.removed()).sum();
+            let traced: usize = nodes.iter().map(|n| n.traced).sum();
+            eprintln!(
+                "{}: layer {layer}: {} {traced} nodes",
+                input.display(),
+                change(removed)
+            );
+        }
+    }
+
+    fn path_w(&self, name: &str) -> PathBuf {
+        self.config.workspace.join(name)
+    }
+}
+
+/// Of view, without padding.
+fn pixels(img: &Image) -> usize {
+    let size = img.dim();
+    size.x * size.y
+}
+
+/// Stores intermediate results of conversion in workspace, with its steps as stages.
+struct SideOutputs<'p, 's, 'a> {
+    config: &'p Config,
+    options: &'p Options,
+    counter: &'p mut usize,
+    stem: &'p str,
+    stages: Stages<'s, 'a>,
+    improvement: Option<Improvement>,
+}
+
+impl Observer for SideOutputs<'_, '_, '_> {
+    fn start(&mut self, name: &str) {
+        self.stages.start(name)
+    }
+    fn count(&mut self, what: &'static str, n: usize) {
+        self.stages.count(what, n)
+    }
+    fn stop(&mut self) {
+        self.stages.stop()
+    }
+
+    fn prepared(&mut self, img: &Image) -> Result<()> {
+        img.store(&self.config.workspace.join(format!("{}.png", self.stem)))
+    }
+
+    fn partitioned(&mut self, index: usize, img: &Image, pn: &Partition) -> Result<()> {
+        let tier = &self.options.tiers[index];
+        let range = tier.range();
         let name_f = match range.1 {
             Some(m) => format!("f({},{})", range.0, m),
             None => format!("f({})", range.0),
         };
-        self.counter += 1;
-        let name = format!("{}_part_eq_{}_{}.png", self.counter, eq.name(), name_f);
+        *self.counter += 1;
+        let name = format!(
+            "{}_part_eq_{}_{}.png",
+            self.counter,
+            tier.eq.build().name(),
+            name_f
+        );
 
-        let path = self.path_w(&name);
-        let t = self.config.timer(&name);
-        let pn = Partition::from_eq(img, eq);
-        t.stop();
+        self.start("from_part");
+        let r = Image::from_part(img, pn);
+        self.stop();
 
-        let t = self.config.timer("filter_by_size");
-        let pn = pn.filter_by_size(range);
-        t.stop();
-
-        let t = self.config.timer("from_part");
-        let r = Image::from_part(img, &pn);
-        t.stop();
-
-        let t = self.config.timer("store");
-        r.store(&path).unwrap();
-        t.stop();
-        pn
+        self.start("store");
+        r.store(&self.config.workspace.join(name))?;
+        self.stop();
+        if index == 0 && self.config.tree {
+            self.start("tree");
+            let tree = Hierarchy::new(pn, img);
+            self.count("nodes", tree.nodes());
+            let svg = hierarchy_svg(img, &tree, self.options);
+            self.stop();
+            let path = format!("{}_tree.svg", self.stem);
+            svg::save(self.config.workspace.join(path), &svg.doc)?;
+        }
+        Ok(())
     }
 
-    fn load(&self, name: &str) -> Image {
-        Image::load(&self.config.dir_source.as_ref().unwrap().join(name), 0).unwrap()
+    fn improved(&mut self, improvement: &Improvement) {
+        self.improvement = Some(*improvement);
     }
+}
 
-    fn path_w(&self, name: &str) -> PathBuf {
-        self.config.workspace.join(name)
+/// "removed n of" or "added n to".
+fn change(removed: isize) -> String {
+    match removed {
+        0.. => format!("removed {removed} of"),
+        _ => format!("added {} to", -removed),
     }
 }
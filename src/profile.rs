@@ -0,0 +1,356 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+
+use rast2vec::Observer;
+use serde_json::json;
+
+use crate::config::ProfileFormat;
+
+thread_local! {
+    /// Bytes allocated by this thread and not freed by it,
+    /// and the most of them since it was reset by a stage.
+    static CURRENT: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+/// System allocator which keeps track of allocated bytes for `Profiler` once enabled.
+pub struct Counting {
+    enabled: AtomicBool,
+}
+
+impl Counting {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    fn grow(&self, size: isize) {
+        if self.enabled.load(Ordering::Relaxed) {
+            grow(size);
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        if !p.is_null() {
+            self.grow(layout.size() as isize);
+        }
+        p
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc_zeroed(layout);
+        if !p.is_null() {
+            self.grow(layout.size() as isize);
+        }
+        p
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.grow(-(layout.size() as isize));
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let p = System.realloc(ptr, layout, new_size);
+        if !p.is_null() {
+            self.grow(new_size as isize - layout.size() as isize);
+        }
+        p
+    }
+}
+
+/// Thread locals may be gone while thread exits, then allocations aren't counted.
+fn grow(size: isize) {
+    let _ = CURRENT.try_with(|current| {
+        current.set(current.get() + size);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(current.get())));
+    });
+}
+
+/// Nested stages of conversion with their durations, counts of processed items
+/// and peak of memory allocated by the thread of stage (not freed by it),
+/// so stages of parallel workers don't see allocations of each other.
+pub struct Profiler {
+    print: bool,
+    start: Instant,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    spans: Vec<Span>,
+    threads: Vec<ThreadId>,
+}
+
+struct Span {
+    name: String,
+    parent: Option<usize>,
+    depth: usize,
+    thread: usize,
+    start: Duration,
+    duration: Duration,
+    counts: BTreeMap<&'static str, usize>,
+    peak_memory: usize,
+    /// Peak of enclosing stage of the same thread before this one started.
+    outer_peak: isize,
+}
+
+impl Profiler {
+    /// With `print` durations of stages are printed to stderr once they stop.
+    pub fn new(print: bool) -> Self {
+        Self {
+            print,
+            start: Instant::now(),
+            inner: Default::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn start<T: ToString>(&self, name: T) -> Stage<'_> {
+        self.open(name.to_string(), None)
+    }
+
+    fn open(&self, name: String, parent: Option<usize>) -> Stage<'_> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = std::thread::current().id();
+        let thread = match inner.threads.iter().position(|&t| t == id) {
+            Some(thread) => thread,
+            None => {
+                inner.threads.push(id);
+                inner.threads.len() - 1
+            }
+        };
+        let depth = parent.map_or(0, |p| inner.spans[p].depth + 1);
+        let outer_peak = PEAK.with(|peak| peak.replace(CURRENT.with(Cell::get)));
+        inner.spans.push(Span {
+            name,
+            parent,
+            depth,
+            thread,
+            start: self.start.elapsed(),
+            duration: Duration::ZERO,
+            counts: BTreeMap::new(),
+            peak_memory: 0,
+            outer_peak,
+        });
+        Stage {
+            profiler: self,
+            index: inner.spans.len() - 1,
+        }
+    }
+
+    fn close(&self, index: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let thread = inner
+            .threads
+            .iter()
+            .position(|&t| t == std::thread::current().id());
+        let span = &mut inner.spans[index];
+        span.duration = self.start.elapsed() - span.start;
+        // Stopped on another thread, peak of this one is unrelated.
+        if thread == Some(span.thread) {
+            let peak = PEAK.with(|peak| peak.replace(peak.get().max(span.outer_peak)));
+            span.peak_memory = peak.max(0) as usize;
+        }
+        if self.print {
+            let name = format!("{}{}", "  ".repeat(span.depth), span.name);
+            eprintln!("{name:50} took {:?}", span.duration);
+        }
+    }
+
+    /// Writes all stages, expects them to be stopped.
+    pub fn write(&self, path: &Path, format: ProfileFormat) -> anyhow::Result<()> {
+        let inner = self.inner.lock().unwrap();
+        let value = match format {
+            ProfileFormat::Json => {
+                let roots = (0..inner.spans.len()).filter(|&i| inner.spans[i].parent.is_none());
+                json!({ "stages": roots.map(|i| inner.tree(i)).collect::<Vec<_>>() })
+            }
+            ProfileFormat::Chrome => {
+                let pid = std::process::id();
+                let events: Vec<_> = (inner.spans.iter())
+                    .map(|s| {
+                        let mut args = json!(s.counts);
+                        args["peak_memory"] = json!(s.peak_memory);
+                        json!({
+                            "name": s.name,
+                            "cat": "rast2vec",
+                            "ph": "X",
+                            "ts": micros(s.start),
+                            "dur": micros(s.duration),
+                            "pid": pid,
+                            "tid": s.thread,
+                            "args": args,
+                        })
+                    })
+                    .collect();
+                json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+            }
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&value)?)?;
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn tree(&self, index: usize) -> serde_json::Value {
+        let s = &self.spans[index];
+        let children =
+            (index + 1..self.spans.len()).filter(|&i| self.spans[i].parent == Some(index));
+        json!({
+            "name": s.name,
+            "thread": s.thread,
+            "start_us": micros(s.start),
+            "duration_us": micros(s.duration),
+            "counts": s.counts,
+            "peak_memory": s.peak_memory,
+            "stages": children.map(|i| self.tree(i)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_nanos() as f64 / 1e3
+}
+
+/// Running stage, stops when dropped. Expects to be stopped on the thread it was started on.
+pub struct Stage<'a> {
+    profiler: &'a Profiler,
+    index: usize,
+}
+
+impl<'a> Stage<'a> {
+    #[must_use]
+    pub fn start<T: ToString>(&self, name: T) -> Stage<'a> {
+        self.profiler.open(name.to_string(), Some(self.index))
+    }
+
+    /// Adds `n` to count of processed items (pixels, regions, nodes...).
+    pub fn count(&self, what: &'static str, n: usize) {
+        let mut inner = self.profiler.inner.lock().unwrap();
+        *inner.spans[self.index].counts.entry(what).or_default() += n;
+    }
+
+    pub fn stop(self) {}
+}
+
+impl Drop for Stage<'_> {
+    fn drop(&mut self) {
+        self.profiler.close(self.index);
+    }
+}
+
+/// Observed steps of conversion as nested stages of `parent`.
+pub struct Stages<'s, 'a> {
+    parent: &'s Stage<'a>,
+    open: Vec<Stage<'a>>,
+}
+
+impl<'s, 'a> Stages<'s, 'a> {
+    pub fn new(parent: &'s Stage<'a>) -> Self {
+        Self {
+            parent,
+            open: vec![],
+        }
+    }
+
+    fn current(&self) -> &Stage<'a> {
+        self.open.last().unwrap_or(self.parent)
+    }
+}
+
+impl Observer for Stages<'_, '_> {
+    fn start(&mut self, name: &str) {
+        let stage = self.current().start(name);
+        self.open.push(stage);
+    }
+    fn count(&mut self, what: &'static str, n: usize) {
+        self.current().count(what, n);
+    }
+    fn stop(&mut self) {
+        self.open.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn profile() {
+        crate::ALLOCATOR.enable();
+        let profiler = Profiler::new(false);
+        let all = profiler.start("all");
+        let stage = all.start("stage");
+        stage.count("pixels", 10);
+        stage.count("pixels", 5);
+        let big = 1 << 22;
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let worker = all.start("worker");
+                let v = vec![1u8; big];
+                worker.count("bytes", v.len());
+            });
+        });
+        let inner = stage.start("inner");
+        drop(vec![1u8; big / 4]);
+        inner.stop();
+        stage.stop();
+        all.stop();
+
+        let dir = std::env::temp_dir();
+        let read = |format, name: &str| -> Value {
+            let path = dir.join(format!("rast2vec_{}_{name}.json", std::process::id()));
+            profiler.write(&path, format).unwrap();
+            let r = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            r
+        };
+
+        let json = read(ProfileFormat::Json, "tree");
+        let roots = json["stages"].as_array().unwrap();
+        assert_eq!(roots.len(), 1);
+        let all = &roots[0];
+        assert_eq!(all["name"], "all");
+        let children = all["stages"].as_array().unwrap();
+        let names: Vec<_> = children
+            .iter()
+            .map(|c| c["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["stage", "worker"]);
+        let (stage, worker) = (&children[0], &children[1]);
+        assert_eq!(stage["counts"]["pixels"], 15);
+        assert_eq!(stage["stages"][0]["name"], "inner");
+        assert!(stage["duration_us"].as_f64().unwrap() >= 0.);
+        assert_ne!(stage["thread"], worker["thread"]);
+        // Allocations of the worker aren't seen by stages of other threads.
+        let peak = |s: &Value| s["peak_memory"].as_u64().unwrap() as usize;
+        assert!(peak(worker) >= big);
+        assert!(peak(&stage["stages"][0]) >= big / 4);
+        assert!(peak(stage) < big, "{stage}");
+
+        let trace = read(ProfileFormat::Chrome, "trace");
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 4);
+        for event in events {
+            assert_eq!(event["ph"], "X");
+            assert!(event["ts"].is_number() && event["dur"].is_number());
+            assert!(event["tid"].is_u64() && event["pid"].is_u64());
+        }
+        let stage = events.iter().find(|e| e["name"] == "stage").unwrap();
+        assert_eq!(stage["args"]["pixels"], 15);
+        assert!(stage["args"]["peak_memory"].is_u64());
+    }
+}
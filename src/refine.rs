@@ -0,0 +1,115 @@
+use crate::core::{Image, Metrics, Partition};
+use crate::{Options, VectorDocument};
+
+/// Passes over the difference of rendered result with the image,
+/// every pass appends a layer of corrective shapes.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Refine {
+    /// The most number of passes.
+    pub passes: usize,
+    /// Pixels differing from render by more than this (L1) are significant,
+    /// and significant neighbours with this or less difference share area.
+    pub diff: u16,
+    /// Areas of significant pixels with less pixels are ignored.
+    pub min_size: usize,
+    /// Stop once render has this PSNR.
+    pub psnr: Option<f64>,
+}
+
+impl Default for Refine {
+    fn default() -> Self {
+        Self {
+            passes: 2,
+            diff: 40,
+            min_size: 5,
+            psnr: None,
+        }
+    }
+}
+
+impl VectorDocument {
+    /// Appends layers until there are no significant areas left, or `psnr` is reached,
+    /// or `passes` are done. Returns number of appended layers.
+    /// Expects `img` of the same size as partitioned image.
+    pub fn refine(&mut self, img: &Image, refine: &Refine, options: &Options) -> usize {
+        let view = img.view();
+        for pass in 0..refine.passes {
+            let render = self.render_padded(self.layers.len());
+            if let Some(psnr) = refine.psnr {
+                let render = render.crop(view.from, view.size);
+                if Metrics::new(&img.crop(view.from, view.size), &render).psnr >= psnr {
+                    return pass;
+                }
+            }
+            let pn = Partition::residual(img, &render, refine.diff)
+                .filter_by_size((refine.min_size, None));
+            let (mut layer, mut counts) = (vec![], vec![]);
+            for shape in Self::shapes(img, &pn, options) {
+                match shape {
+                    Ok((shape, _)) if !options.visible(&shape) => {}
+                    Ok((shape, n)) => {
+                        layer.push(shape);
+                        counts.push(n);
+                    }
+                    Err(e) => self.errors.push(e),
+                }
+            }
+            if layer.is_empty() {
+                return pass;
+            }
+            self.layers.push(layer);
+            self.nodes.push(counts);
+        }
+        refine.passes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::equivalance::EqKind;
+    use crate::core::{Color, Quantize, V};
+    use crate::{convert, Tier};
+
+    #[test]
+    fn refine() {
+        // Gray square with a spot too small for the tier.
+        let mut img = Image::new(V::new(12, 12), 0);
+        for v in img.view().iter() {
+            img[v] = Color::new([100, 100, 100, 255]);
+        }
+        for v in [(4, 4), (4, 5), (5, 4), (5, 5), (6, 5)] {
+            img[V::from(v)] = Color::new([250, 20, 20, 255]);
+        }
+        let mut options = Options {
+            tiers: vec![Tier::new(EqKind::L1Diff { diff: 10 }, 20, None)],
+            pad: 1,
+            ..Default::default()
+        };
+
+        let doc = convert(&img, &options).unwrap();
+        assert_eq!(doc.layers.len(), 1);
+        let before = doc.quality(&img);
+
+        options.refine = Some(Refine::default());
+        let doc = convert(&img, &options).unwrap();
+        assert_eq!(doc.layers.len(), 2);
+        assert_eq!(doc.layers[1].len(), 1);
+        assert_eq!(doc.layers[1][0].color, Color::new([250, 20, 20, 255]));
+        let after = doc.quality(&img);
+        assert!(
+            after.metrics.psnr > before.metrics.psnr + 10.,
+            "{before:?} {after:?}"
+        );
+
+        // Spot lost by quantization is refined against the source.
+        options.quantize = Some(Quantize {
+            palette: Some(vec![Color::new([100, 100, 100, 255])]),
+            ..Default::default()
+        });
+        let doc = convert(&img, &options).unwrap();
+        assert_eq!(doc.layers.len(), 2);
+        assert_eq!(doc.layers[1][0].color, Color::new([250, 20, 20, 255]));
+    }
+}
@@ -1,6 +1,12 @@
-#[derive(Default, Clone, Copy, PartialEq, Eq, derive_new::new)]
+/// Rgba, layout matches `image::Rgba<u8>`.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash, derive_new::new)]
+#[repr(transparent)]
 pub struct Color([u8; 4]);
 
+// Safety: `Color` is a transparent wrapper of bytes.
+unsafe impl bytemuck::Zeroable for Color {}
+unsafe impl bytemuck::Pod for Color {}
+
 impl Color {
     pub fn diff(self, other: Color) -> [i16; 4] {
         [
@@ -20,9 +26,49 @@ impl Color {
             .unwrap()
     }
 
+    pub fn rgba(self) -> [u8; 4] {
+        self.0
+    }
+    pub fn rgb(self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+    pub fn alpha(self) -> u8 {
+        self.0[3]
+    }
+
+    /// Alpha is not included, see `format_svg_opacity`.
     pub fn format_svg(self) -> String {
         format!("#{:02x}{:02x}{:02x}", self.0[0], self.0[1], self.0[2])
     }
+    /// `None` for opaque color.
+    pub fn format_svg_opacity(self) -> Option<String> {
+        let alpha = self.alpha() as f32 / 255.;
+        (self.alpha() < 255).then(|| format!("{}", (alpha * 1000.).round() / 1000.))
+    }
+}
+
+/// Hex `#rrggbb` or `#rrggbbaa`, `#` is optional.
+impl std::str::FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            anyhow::bail!("bad color '{s}', expected '#rrggbb' or '#rrggbbaa'");
+        }
+        let mut r = [255; 4];
+        for (i, c) in r.iter_mut().enumerate().take(hex.len() / 2) {
+            *c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+        }
+        Ok(Self(r))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 impl std::ops::Add<Color> for [usize; 4] {
@@ -44,9 +90,9 @@ impl From<image::Rgba<u8>> for Color {
     }
 }
 
-impl Into<image::Rgba<u8>> for Color {
-    fn into(self) -> image::Rgba<u8> {
-        image::Rgba(self.0)
+impl From<Color> for image::Rgba<u8> {
+    fn from(value: Color) -> Self {
+        image::Rgba(value.0)
     }
 }
 
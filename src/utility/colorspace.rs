@@ -0,0 +1,164 @@
+use super::Color;
+
+/// CIELAB (D65 white point), `l` in `0..100`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+/// Oklab, `l` in `0..1`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Oklab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Color {
+    /// Channels of linear sRGB in `0..1`, alpha is dropped.
+    pub fn linear(self) -> [f32; 3] {
+        self.rgb().map(|c| {
+            let c = c as f32 / 255.;
+            match c <= 0.04045 {
+                true => c / 12.92,
+                false => ((c + 0.055) / 1.055).powf(2.4),
+            }
+        })
+    }
+
+    pub fn lab(self) -> Lab {
+        let [r, g, b] = self.linear().map(|c| c as f64);
+        let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+        let f = |t: f64| {
+            const D: f64 = 6. / 29.;
+            match t > D * D * D {
+                true => t.cbrt(),
+                false => t / (3. * D * D) + 4. / 29.,
+            }
+        };
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        Lab {
+            l: (116. * fy - 16.) as f32,
+            a: (500. * (fx - fy)) as f32,
+            b: (200. * (fy - fz)) as f32,
+        }
+    }
+
+    pub fn oklab(self) -> Oklab {
+        let [r, g, b] = self.linear().map(|c| c as f64);
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+        Oklab {
+            l: (0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s) as f32,
+            a: (1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s) as f32,
+            b: (0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s) as f32,
+        }
+    }
+}
+
+impl Lab {
+    /// Euclidean distance, CIE76.
+    pub fn delta_e76(self, other: Lab) -> f32 {
+        let (dl, da, db) = (self.l - other.l, self.a - other.a, self.b - other.b);
+        (dl * dl + da * da + db * db).sqrt()
+    }
+
+    /// CIEDE2000 with unit weights.
+    pub fn delta_e2000(self, other: Lab) -> f32 {
+        use std::f64::consts::PI;
+        let (l1, a1, b1) = (self.l as f64, self.a as f64, self.b as f64);
+        let (l2, a2, b2) = (other.l as f64, other.a as f64, other.b as f64);
+        let pow7 = |x: f64| x.powi(7);
+
+        let c = (a1.hypot(b1) + a2.hypot(b2)) / 2.;
+        let g = 0.5 * (1. - (pow7(c) / (pow7(c) + pow7(25.))).sqrt());
+        let (a1, a2) = (a1 * (1. + g), a2 * (1. + g));
+        let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+        // Hue angle in degrees, zero for achromatic colors.
+        let hue = |b: f64, a: f64| match b == 0. && a == 0. {
+            true => 0.,
+            false => b.atan2(a).to_degrees().rem_euclid(360.),
+        };
+        let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+        let dl = l2 - l1;
+        let dc = c2 - c1;
+        let dh = match c1 * c2 == 0. {
+            true => 0.,
+            false if (h2 - h1).abs() <= 180. => h2 - h1,
+            false if h2 - h1 > 180. => h2 - h1 - 360.,
+            false => h2 - h1 + 360.,
+        };
+        let dh = 2. * (c1 * c2).sqrt() * (dh.to_radians() / 2.).sin();
+
+        let l = (l1 + l2) / 2.;
+        let c = (c1 + c2) / 2.;
+        let h = match c1 * c2 == 0. {
+            true => h1 + h2,
+            false if (h1 - h2).abs() <= 180. => (h1 + h2) / 2.,
+            false if h1 + h2 < 360. => (h1 + h2 + 360.) / 2.,
+            false => (h1 + h2 - 360.) / 2.,
+        };
+        let t = 1. - 0.17 * (h - 30.).to_radians().cos()
+            + 0.24 * (2. * h).to_radians().cos()
+            + 0.32 * (3. * h + 6.).to_radians().cos()
+            - 0.20 * (4. * h - 63.).to_radians().cos();
+        let theta = 30. * (-((h - 275.) / 25.).powi(2)).exp();
+        let rc = 2. * (pow7(c) / (pow7(c) + pow7(25.))).sqrt();
+        let sl = 1. + 0.015 * (l - 50.).powi(2) / (20. + (l - 50.).powi(2)).sqrt();
+        let sc = 1. + 0.045 * c;
+        let sh = 1. + 0.015 * c * t;
+        let rt = -(2. * theta * PI / 180.).sin() * rc;
+
+        let (l, c, h) = (dl / sl, dc / sc, dh / sh);
+        (l * l + c * c + h * h + rt * c * h).sqrt() as f32
+    }
+}
+
+impl Oklab {
+    pub fn dist(self, other: Oklab) -> f32 {
+        let (dl, da, db) = (self.l - other.l, self.a - other.a, self.b - other.b);
+        (dl * dl + da * da + db * db).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let white = Color::new([255, 255, 255, 255]);
+        let lab = white.lab();
+        assert!((lab.l - 100.).abs() < 1e-3 && lab.a.abs() < 1e-3 && lab.b.abs() < 1e-3);
+        let ok = white.oklab();
+        assert!((ok.l - 1.).abs() < 1e-3 && ok.a.abs() < 1e-3 && ok.b.abs() < 1e-3);
+
+        let red = Color::new([255, 0, 0, 255]).lab();
+        assert!((red.l - 53.24).abs() < 0.01 && (red.a - 80.09).abs() < 0.01);
+        assert!((red.b - 67.20).abs() < 0.01);
+
+        // Pairs from Sharma, Wu, Dalal "The CIEDE2000 color-difference formula".
+        let pairs = [
+            ([50., 2.6772, -79.7751], [50., 0., -82.7485], 2.0425),
+            ([50., 2.5, 0.], [73., 25., -18.], 27.1492),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+            ([2.0776, 0.0795, -1.135], [0.9033, -0.0636, -0.5514], 0.9082),
+        ];
+        let lab = |[l, a, b]: [f32; 3]| Lab { l, a, b };
+        for (x, y, de) in pairs {
+            let (x, y) = (lab(x), lab(y));
+            assert!((x.delta_e2000(y) - de).abs() < 1e-3, "{x:?} {y:?}");
+            assert!((y.delta_e2000(x) - de).abs() < 1e-3, "{y:?} {x:?}");
+        }
+    }
+}
@@ -1,9 +1,11 @@
 mod color;
+mod colorspace;
 mod rect;
 mod vector;
 
 pub mod debug;
 
 pub use color::Color;
+pub use colorspace::{Lab, Oklab};
 pub use rect::{Rect, TwoDimIter};
 pub use vector::V;
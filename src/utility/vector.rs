@@ -38,6 +38,9 @@ impl V {
     pub fn min_common(a: V, b: V) -> V {
         V::new(std::cmp::min(a.y, b.y), std::cmp::min(a.x, b.x))
     }
+    pub fn max_common(a: V, b: V) -> V {
+        V::new(std::cmp::max(a.y, b.y), std::cmp::max(a.x, b.x))
+    }
 }
 
 impl V {
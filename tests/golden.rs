@@ -0,0 +1,127 @@
+//! Converts every image of `data/` by default pipeline and compares the result with
+//! baselines in `tests/golden/`: quality (`<name>.json`) and svg structure (`<name>.txt`).
+//! Run with `BLESS=1` to store current results as baselines.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rast2vec::core::Image;
+use rast2vec::{Options, Quality};
+
+/// The most PSNR (dB) and SSIM may drop below baseline.
+const PSNR_DROP: f64 = 0.1;
+const SSIM_DROP: f64 = 0.002;
+
+#[test]
+fn golden() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let pipeline: toml::Table = toml::from_str(include_str!("../pipelines/default.toml")).unwrap();
+    let options = Options::from_toml(&toml::to_string(&pipeline["options"]).unwrap()).unwrap();
+    let bless = std::env::var_os("BLESS").is_some();
+
+    let mut inputs: Vec<PathBuf> = glob::glob(root.join("data/*.png").to_str().unwrap())
+        .unwrap()
+        .map(|p| p.unwrap())
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    let mut failures = vec![];
+    for input in &inputs {
+        let name = input.file_stem().unwrap().to_string_lossy();
+        let img = Image::load(input, 0).unwrap();
+        let doc = rast2vec::convert(&img, &options).unwrap();
+        let quality = doc.quality(&img);
+        let structure = structure(&doc.svg().doc.to_string());
+
+        let baseline = root.join("tests/golden").join(name.as_ref());
+        let (json, txt) = (
+            baseline.with_extension("json"),
+            baseline.with_extension("txt"),
+        );
+        if bless {
+            std::fs::create_dir_all(baseline.parent().unwrap()).unwrap();
+            std::fs::write(&json, serde_json::to_string_pretty(&quality).unwrap()).unwrap();
+            std::fs::write(&txt, structure).unwrap();
+            continue;
+        }
+        let (Ok(expected), Ok(expected_structure)) = (
+            std::fs::read_to_string(&json),
+            std::fs::read_to_string(&txt),
+        ) else {
+            failures.push(format!("{name}: no baseline"));
+            continue;
+        };
+        let expected: Quality = serde_json::from_str(&expected).unwrap();
+        failures.extend(compare(&quality, &expected).map(|e| format!("{name}: {e}")));
+        if structure != expected_structure {
+            let same = (structure.lines().zip(expected_structure.lines()))
+                .take_while(|(a, b)| a == b)
+                .count();
+            failures.push(format!(
+                "{name}: svg structure differs at line {}",
+                same + 1
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\nrun with BLESS=1 to accept changes",
+        failures.join("\n")
+    );
+}
+
+fn compare(actual: &Quality, expected: &Quality) -> Option<String> {
+    let (a, e) = (actual.metrics, expected.metrics);
+    let mut r = vec![];
+    if a.psnr < e.psnr - PSNR_DROP {
+        r.push(format!("psnr {:.3} < {:.3}", a.psnr, e.psnr));
+    }
+    if a.ssim < e.ssim - SSIM_DROP {
+        r.push(format!("ssim {:.4} < {:.4}", a.ssim, e.ssim));
+    }
+    for (what, a, e) in [
+        ("nodes", actual.nodes, expected.nodes),
+        ("paths", actual.paths, expected.paths),
+        ("svg bytes", actual.svg_bytes, expected.svg_bytes),
+    ] {
+        if a > e {
+            r.push(format!("{what} {a} > {e}"));
+        }
+    }
+    (!r.is_empty()).then(|| r.join(", "))
+}
+
+/// One line per element: name, sorted attributes, and numbers of commands of path data.
+fn structure(svg: &str) -> String {
+    use svg::node::element::path::Data;
+    use svg::parser::Event;
+
+    let mut r = String::new();
+    for event in svg::read(svg).unwrap() {
+        let Event::Tag(name, _, attributes) = event else {
+            continue;
+        };
+        let mut line = name.to_string();
+        let attributes: BTreeMap<_, _> = attributes.iter().collect();
+        for (key, value) in attributes {
+            let value = match key.as_str() {
+                "d" => {
+                    let mut counts = BTreeMap::new();
+                    for command in Data::parse(value).unwrap().iter() {
+                        let command = format!("{command:?}");
+                        let kind = command.split('(').next().unwrap().to_string();
+                        *counts.entry(kind).or_insert(0) += 1;
+                    }
+                    let counts: Vec<_> = counts.iter().map(|(k, n)| format!("{k}:{n}")).collect();
+                    counts.join(",")
+                }
+                _ => value.to_string(),
+            };
+            line += &format!(" {key}={value}");
+        }
+        r += &line;
+        r.push('\n');
+    }
+    r
+}